@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use super::config::SharedSettings;
+use super::discord::{self, Context, EventHandler, Message};
+
+// Logs every new message together with where it was sent
+struct MessageLogger;
+
+impl EventHandler for MessageLogger {
+    fn on_message(&self, ctx: &Context, msg: &Message) {
+        let guild = msg.guild_id.as_ref()
+            .and_then(|id| ctx.cache.guild(id))
+            .map(|g| g.name.as_str())
+            .unwrap_or("DM");
+        let channel = ctx.cache.channel(&msg.channel_id)
+            .map(|c| c.name.as_str())
+            .unwrap_or(&msg.channel_id);
+        info!("New message in {}#{} from {}: {}", guild, channel, msg.author.username, msg.content);
+    }
+}
+
+// Answers messages starting with the command prefix of the guild. The prefix
+// is read from the shared settings so reloaded prefixes are used right away
+struct Commands {
+    settings: SharedSettings,
+}
+
+impl EventHandler for Commands {
+    fn on_message(&self, ctx: &Context, msg: &Message) {
+        if msg.author.bot {
+            return;
+        }
+        let settings = self.settings.read().expect("Settings lock poisoned");
+        let prefix = settings.prefix_for(msg.guild_id.as_deref());
+        let command = match msg.content.strip_prefix(prefix) {
+            Some(c) => c.split_whitespace().next(),
+            None => return,
+        };
+        let reply = match command {
+            Some("ping") => String::from("Pong!"),
+            Some("shards") => shard_statuses(ctx),
+            _ => return,
+        };
+        if let Err(e) = discord::send_message(&ctx.http, &msg.channel_id, &reply) {
+            warn!("Could not answer command in channel {}: {}", msg.channel_id, e);
+        }
+    }
+}
+
+// Lists the status of every shard, e.g. "Shard 0/2, shards: 0 Ready, 1 Connecting"
+fn shard_statuses(ctx: &Context) -> String {
+    let statuses: Vec<String> = ctx.shards.statuses().iter()
+        .enumerate()
+        .map(|(id, status)| format!("{} {}", id, status))
+        .collect();
+    format!("Shard {}/{}, shards: {}", ctx.shard, ctx.shards.count(), statuses.join(", "))
+}
+
+// Handlers that make up the bot's logic, in the order they are called
+pub fn handlers(settings: &SharedSettings) -> Vec<Box<dyn EventHandler>> {
+    vec![
+        Box::new(MessageLogger),
+        Box::new(Commands { settings: Arc::clone(settings) }),
+    ]
+}
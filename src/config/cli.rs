@@ -0,0 +1,210 @@
+use std::fmt;
+
+use super::{ConfigError, ConfigErrorKind, CONFIG_KEYS, ENV_PREFIX};
+use super::super::error::Error;
+
+#[derive(Debug, Clone)]
+pub enum Flag{
+    ConfigFile(String),
+    // A config key and value given as --<key> <value>
+    Setting(String, String),
+    // Fail on missing values instead of prompting for them
+    NonInteractive,
+}
+
+// What the binary should do, given as the first argument that isn't a flag
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    // Connects the bot and handles events until it stops
+    Run,
+    // Loads the settings and validates them against the discord api
+    CheckConfig,
+    // Lists the channels of the configured guild
+    ListChannels,
+    // Sends a single message to a channel
+    Send { channel: String, text: String },
+    // Shows the gateway url, recommended shard count and session start limit
+    GatewayInfo,
+    Help,
+    Version,
+}
+
+// Parsed command line, the flags are applied when the settings are loaded
+#[derive(Debug, Clone)]
+pub struct Arguments {
+    pub command: Command,
+    pub flags: Vec<Flag>,
+}
+
+impl Command {
+    // Name of the command as given on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Run => "run",
+            Command::CheckConfig => "check-config",
+            Command::ListChannels => "list-channels",
+            Command::Send { .. } => "send",
+            Command::GatewayInfo => "gateway-info",
+            Command::Help => "help",
+            Command::Version => "version",
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// Commands with their arguments and descriptions, as shown in --help
+const COMMANDS: [(&str, &str); 5] = [
+    ("run", "Connect the bot and handle messages (default)"),
+    ("check-config", "Validate the settings against the discord api"),
+    ("list-channels", "List the channels of the configured guild"),
+    ("send <channel> <text>", "Send a message to a channel"),
+    ("gateway-info", "Show the gateway url, shard count and session start limit"),
+];
+
+// Placeholder and description of a setting's flag, as shown in --help
+fn setting_help(key: &str) -> (&'static str, &'static str) {
+    match key {
+        "client" => ("ID", "Client id of the bot's application"),
+        "guild" => ("ID", "Id of the guild the bot serves"),
+        "secret" => ("SECRET", "Client secret of the application"),
+        "token" => ("TOKEN", "Bot token"),
+        "secret_file" => ("FILE", "Read the client secret from a file"),
+        "token_file" => ("FILE", "Read the bot token from a file"),
+        "intents" => ("INTENTS", "Gateway intents, e.g. guilds,guild_messages"),
+        "delivery" => ("MODE", "How messages are received: gateway or polling [default: gateway]"),
+        "api_url" => ("URL", "Base url of the REST api [default: https://discordapp.com/api]"),
+        "api_version" => ("VERSION", "Version of the REST api and gateway [default: 6]"),
+        "gateway_url" => ("URL", "Gateway url to connect to instead of asking the api"),
+        "shards" => ("COUNT", "Number of gateway shards or auto [default: auto]"),
+        "log_level" => ("LEVEL", "off, error, warn, info, debug or trace [default: info]"),
+        "prefix" => ("PREFIX", "Prefix of bot commands [default: !]"),
+        "status" => ("STATUS", "online, idle, dnd or invisible [default: online]"),
+        "activity" => ("NAME", "Name of the game the bot is shown playing"),
+        "compress" => ("BOOL", "Compress gateway messages with zlib-stream [default: false]"),
+        "encoding" => ("ENCODING", "Gateway message encoding: json or etf [default: json]"),
+        "session_limit_file" => ("FILE", "Where the gateway session start limit is kept between restarts [default: .ruuster_session_limit.json]"),
+        _ => ("VALUE", ""),
+    }
+}
+
+// Help text listing every command and flag
+pub fn usage() -> String {
+    let mut options = vec![
+        (String::from("-f, --config <FILE>"), "Read settings from a TOML or key=value file"),
+        (String::from("--non-interactive"), "Fail on missing settings instead of prompting"),
+        (String::from("-h, --help"), "Print this help"),
+        (String::from("-V, --version"), "Print the version"),
+    ];
+    for key in CONFIG_KEYS.iter() {
+        let (value, description) = setting_help(key);
+        options.push((format!("--{} <{}>", key.replace('_', "-"), value), description));
+    }
+
+    let mut usage = format!(
+        "{} {}\n\nUSAGE:\n    {} [OPTIONS] [COMMAND]\n\nCOMMANDS:\n",
+        env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), env!("CARGO_PKG_NAME"));
+    for (command, description) in COMMANDS.iter() {
+        usage.push_str(&format!("    {:<24}{}\n", command, description));
+    }
+    usage.push_str("\nOPTIONS:\n");
+    for (option, description) in options {
+        usage.push_str(&format!("    {:<30}{}\n", option, description));
+    }
+    usage.push_str(&format!(
+        "\nEvery setting can also be given as the environment variable {}<KEY>, e.g. {}LOG_LEVEL\n",
+        ENV_PREFIX, ENV_PREFIX));
+    usage
+}
+
+fn invalid_argument(msg: String) -> Error {
+    Error::Config(ConfigError::new(ConfigErrorKind::InvalidArgument(msg)))
+}
+
+// Builds the command from its name and the arguments that followed it
+fn parse_command(name: &str, mut args: Vec<String>) -> Result<Command, Error> {
+    let expected = match name {
+        "send" => 2,
+        _ => 0,
+    };
+    if args.len() != expected {
+        return Err(invalid_argument(match expected {
+            0 => format!("Unexpected argument for {}: {}", name, args[0]),
+            _ => format!("Usage: {} send <channel> <text>", env!("CARGO_PKG_NAME")),
+        }));
+    }
+    let command = match name {
+        "run" => Command::Run,
+        "check-config" => Command::CheckConfig,
+        "list-channels" => Command::ListChannels,
+        "send" => {
+            let text = args.pop().unwrap_or_default();
+            let channel = args.pop().unwrap_or_default();
+            Command::Send { channel, text }
+        },
+        "gateway-info" => Command::GatewayInfo,
+        "help" => Command::Help,
+        _ => return Err(invalid_argument(format!("Unknown command: {}", name))),
+    };
+    Ok(command)
+}
+
+// Parses the command line arguments, without the binary name. Flags may be
+// given anywhere, as --<flag> <value> or --<flag>=<value>, and setting flags
+// accept both - and _ in the key. Arguments after -- are never read as flags
+pub fn parse<I: Iterator<Item = String>>(mut args_iter: I) -> Result<Arguments, Error> {
+    let mut flags: Vec<Flag> = Vec::new();
+    let mut positional: Vec<String> = Vec::new();
+    while let Some(arg) = args_iter.next() {
+        if arg == "--" {
+            positional.extend(&mut args_iter);
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            positional.push(arg);
+            continue;
+        }
+
+        let (name, inline_value) = match arg.find('=') {
+            Some(i) => (&arg[..i], Some(String::from(&arg[i + 1..]))),
+            None => (arg.as_str(), None),
+        };
+        match name {
+            "-h" | "--help" => return Ok(Arguments { command: Command::Help, flags }),
+            "-V" | "--version" => return Ok(Arguments { command: Command::Version, flags }),
+            "--non-interactive" => {
+                flags.push(Flag::NonInteractive);
+                continue;
+            },
+            _ => {},
+        }
+
+        let key = match name {
+            "-f" | "--config" => None,
+            _ => match name.strip_prefix("--").map(|k| k.replace('-', "_")) {
+                Some(key) if CONFIG_KEYS.contains(&key.as_str()) => Some(key),
+                _ => return Err(invalid_argument(format!("Unknown argument: {}", arg))),
+            },
+        };
+        let val = match inline_value.or_else(|| args_iter.next()) {
+            Some(val) => val,
+            None => return Err(invalid_argument(format!("Value must follow flag {}", name))),
+        };
+        match key {
+            Some(key) => flags.push(Flag::Setting(key, val)),
+            None => flags.push(Flag::ConfigFile(val)),
+        }
+    }
+
+    let command = if positional.is_empty() {
+        Command::Run
+    } else {
+        let name = positional.remove(0);
+        parse_command(&name, positional)?
+    };
+    Ok(Arguments { command, flags })
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+
+// Error in the configuration, with the line number when it comes from a config file
+#[derive(Debug, PartialEq)]
+pub struct ConfigError {
+    pub line: Option<usize>,
+    pub kind: ConfigErrorKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigErrorKind {
+    // Line is not a comment and has no '='
+    MissingSeparator,
+    // Nothing before the '='
+    EmptyKey,
+    // Quoted value without a closing quote
+    UnterminatedQuote,
+    // Something other than a comment after a quoted value
+    TrailingCharacters(String),
+    // TOML file that could not be deserialized, the message contains the line
+    Toml(String),
+    // Bad command line argument
+    InvalidArgument(String),
+    // Required settings that weren't given by any source
+    Missing(Vec<&'static str>),
+    InvalidValue { key: &'static str, value: String, reason: String },
+}
+
+impl ConfigError {
+    // Error that doesn't come from a specific line
+    pub fn new(kind: ConfigErrorKind) -> ConfigError {
+        ConfigError { line: None, kind }
+    }
+}
+
+impl fmt::Display for ConfigErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigErrorKind::MissingSeparator => write!(f, "expected key=value"),
+            ConfigErrorKind::EmptyKey => write!(f, "missing key before '='"),
+            ConfigErrorKind::UnterminatedQuote => write!(f, "quoted value is missing its closing quote"),
+            ConfigErrorKind::TrailingCharacters(s) => write!(f, "unexpected \"{}\" after quoted value", s),
+            ConfigErrorKind::Toml(msg) => write!(f, "{}", msg),
+            ConfigErrorKind::InvalidArgument(msg) => write!(f, "{}", msg),
+            ConfigErrorKind::Missing(keys) => write!(f,
+                "missing required settings: {}. Set them with --<key>, {}<KEY> or in a config file",
+                keys.join(", "), super::ENV_PREFIX),
+            ConfigErrorKind::InvalidValue { key, value, reason } => {
+                write!(f, "invalid {} \"{}\": {}", key, value, reason)
+            },
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
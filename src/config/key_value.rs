@@ -0,0 +1,170 @@
+use super::config_error::{ConfigError, ConfigErrorKind};
+
+// A key and value from a line in a key=value config file
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+}
+
+// Parses the contents of a key=value config file. Blank lines and lines
+// starting with # are skipped, whitespace around keys and values is ignored and
+// values may be quoted with " or '. Values are split from the key at the
+// first '=' so they may contain '=' themselves. Unquoted values end at a #
+// preceded by whitespace
+pub fn parse(contents: &str) -> Result<Vec<Entry>, ConfigError> {
+    let mut entries = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |kind| ConfigError { line: Some(line_number), kind };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let separator = match line.find('=') {
+            Some(s) => s,
+            None => return Err(error(ConfigErrorKind::MissingSeparator)),
+        };
+        let key = line[..separator].trim();
+        if key.is_empty() {
+            return Err(error(ConfigErrorKind::EmptyKey));
+        }
+        let value = parse_value(line[separator + 1..].trim()).map_err(error)?;
+
+        entries.push(Entry {
+            line: line_number,
+            key: String::from(key),
+            value,
+        });
+    }
+    Ok(entries)
+}
+
+fn parse_value(value: &str) -> Result<String, ConfigErrorKind> {
+    let quote = match value.chars().next() {
+        Some(q) if q == '"' || q == '\'' => q,
+        _ => return Ok(String::from(strip_comment(value))),
+    };
+
+    // Double quoted values may escape quotes and backslashes with a backslash
+    let mut parsed = String::new();
+    let mut chars = value.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        if c == quote {
+            let rest = value[i + 1..].trim();
+            if !rest.is_empty() && !rest.starts_with('#') {
+                return Err(ConfigErrorKind::TrailingCharacters(String::from(rest)));
+            }
+            return Ok(parsed);
+        }
+        if c == '\\' && quote == '"' {
+            match chars.next() {
+                Some((_, escaped)) => parsed.push(escaped),
+                None => break,
+            }
+        } else {
+            parsed.push(c);
+        }
+    }
+    Err(ConfigErrorKind::UnterminatedQuote)
+}
+
+fn strip_comment(value: &str) -> &str {
+    let comment = value.char_indices()
+        .find(|&(i, c)| c == '#' && i > 0 && value[..i].ends_with(char::is_whitespace));
+    match comment {
+        Some((i, _)) => value[..i].trim_end(),
+        None => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(line: usize, key: &str, value: &str) -> Entry {
+        Entry { line, key: String::from(key), value: String::from(value) }
+    }
+
+    fn error(contents: &str) -> ConfigError {
+        parse(contents).expect_err("Contents are malformed")
+    }
+
+    #[test]
+    fn parses_entries_with_line_numbers() {
+        let contents = "# comment\n\nclient = 123\n  guild=456  \n";
+        assert_eq!(parse(contents).unwrap(), vec![entry(3, "client", "123"), entry(4, "guild", "456")]);
+    }
+
+    #[test]
+    fn value_may_contain_separator() {
+        assert_eq!(parse("token=abc=def==").unwrap(), vec![entry(1, "token", "abc=def==")]);
+    }
+
+    #[test]
+    fn empty_value_is_allowed() {
+        assert_eq!(parse("activity=").unwrap(), vec![entry(1, "activity", "")]);
+    }
+
+    #[test]
+    fn comments_after_values() {
+        let contents = "prefix = ! # the prefix\nactivity = C#\nstatus = \"idle\" # quoted\n";
+        assert_eq!(parse(contents).unwrap(), vec![
+            entry(1, "prefix", "!"),
+            entry(2, "activity", "C#"),
+            entry(3, "status", "idle"),
+        ]);
+    }
+
+    #[test]
+    fn quoted_values() {
+        let contents = "a = \"  spaced # not a comment \"\nb = 'single \\ quoted'\nc = \"escaped \\\" quote\"\n";
+        assert_eq!(parse(contents).unwrap(), vec![
+            entry(1, "a", "  spaced # not a comment "),
+            entry(2, "b", "single \\ quoted"),
+            entry(3, "c", "escaped \" quote"),
+        ]);
+    }
+
+    #[test]
+    fn missing_separator() {
+        assert_eq!(error("client=1\n\nguild 2\n"), ConfigError {
+            line: Some(3),
+            kind: ConfigErrorKind::MissingSeparator,
+        });
+    }
+
+    #[test]
+    fn empty_key() {
+        assert_eq!(error("# comment\n  = value"), ConfigError {
+            line: Some(2),
+            kind: ConfigErrorKind::EmptyKey,
+        });
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        assert_eq!(error("a=1\nb=\"open"), ConfigError {
+            line: Some(2),
+            kind: ConfigErrorKind::UnterminatedQuote,
+        });
+        assert_eq!(error("a='open").kind, ConfigErrorKind::UnterminatedQuote);
+        // A trailing escape doesn't close the quote
+        assert_eq!(error("a=\"open\\\"").kind, ConfigErrorKind::UnterminatedQuote);
+    }
+
+    #[test]
+    fn text_after_closing_quote() {
+        assert_eq!(error("\n\na = \"value\" extra # comment"), ConfigError {
+            line: Some(3),
+            kind: ConfigErrorKind::TrailingCharacters(String::from("extra # comment")),
+        });
+    }
+
+    #[test]
+    fn error_display_contains_line() {
+        assert_eq!(error("a=1\nbad").to_string(), "line 2: expected key=value");
+    }
+}
@@ -0,0 +1,690 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+use log::LevelFilter;
+use reqwest::Url;
+use serde::Deserialize;
+
+use super::discord::Intents;
+use super::error::Error;
+
+mod cli;
+mod config_error;
+mod key_value;
+mod reload;
+mod secret;
+mod toml_file;
+
+pub use self::cli::{usage, Arguments, Command};
+pub use self::config_error::{ConfigError, ConfigErrorKind};
+pub use self::reload::{watch, SharedSettings};
+pub use self::secret::Secret;
+use self::cli::Flag;
+
+const DEFAULT_API_URL: &str = "https://discordapp.com/api";
+const DEFAULT_API_VERSION: u32 = 6;
+const DEFAULT_PREFIX: &str = "!";
+const DEFAULT_STATUS: &str = "online";
+const DEFAULT_SESSION_LIMIT_FILE: &str = ".ruuster_session_limit.json";
+
+// Statuses the bot can be shown with
+const STATUSES: [&str; 4] = ["online", "idle", "dnd", "invisible"];
+
+// Every config key. Each key can also be given as the command line flag
+// --<key> and the environment variable RUUSTER_<KEY>
+const CONFIG_KEYS: [&str; 19] = [
+    "client", "guild", "secret", "token", "secret_file", "token_file", "intents",
+    "delivery", "api_url", "api_version", "gateway_url", "shards", "log_level", "prefix",
+    "status", "activity", "session_limit_file", "compress", "encoding",
+];
+
+// Settings that must be given, all other settings have default values
+const REQUIRED_KEYS: [&str; 4] = ["client", "guild", "secret", "token"];
+
+// Prefix of the environment variables, e.g. RUUSTER_TOKEN sets the token
+const ENV_PREFIX: &str = "RUUSTER_";
+
+// How new messages are delivered to the bot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delivery {
+    // Message create events over the websocket gateway
+    Gateway,
+    // Polling every text channel over the REST api
+    Polling,
+}
+
+// Encoding of gateway messages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Json,
+    // Erlang external term format, sent in binary messages
+    Etf,
+}
+
+#[derive(Debug)]
+pub struct SettingsInitializer{
+    pub client: Option<String>,
+    pub guild: Option<String>,
+    pub secret: Option<String>,
+    pub token: Option<String>,
+    // Files containing the secret and token, e.g. mounted docker secrets
+    pub secret_file: Option<String>,
+    pub token_file: Option<String>,
+    pub intents: Option<String>,
+    pub delivery: Option<String>,
+    pub api_url: Option<String>,
+    pub api_version: Option<String>,
+    pub gateway_url: Option<String>,
+    pub shards: Option<String>,
+    pub log_level: Option<String>,
+    pub prefix: Option<String>,
+    pub status: Option<String>,
+    pub activity: Option<String>,
+    pub session_limit_file: Option<String>,
+    pub compress: Option<String>,
+    pub encoding: Option<String>,
+    // Only set from TOML config files
+    pub guilds: HashMap<String, GuildSettings>,
+}
+
+// Settings that can be overridden for a single guild
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GuildSettings{
+    pub prefix: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings{
+    pub client: String,
+    pub guild: String,
+    // OAuth2 client secret, not needed by the bot itself yet
+    #[allow(dead_code)]
+    pub secret: Secret,
+    pub token: Secret,
+    pub intents: Intents,
+    pub delivery: Delivery,
+    // Base url of the REST api without version, e.g. a local mock server or a proxy
+    pub api_url: String,
+    // Version of both the REST api and the gateway
+    pub api_version: u32,
+    // Connects to this gateway url instead of the one given by the api
+    pub gateway_url: Option<String>,
+    // Number of gateway shards, decided by discord if not set or auto
+    pub shards: Option<u32>,
+    pub log_level: LevelFilter,
+    // Prefix of bot commands in messages
+    pub prefix: String,
+    // Presence shown for the bot, status is one of STATUSES and activity is
+    // the name of the game it is playing
+    pub status: String,
+    pub activity: Option<String>,
+    // Where the remaining gateway session starts are kept between restarts
+    pub session_limit_file: String,
+    // Gateway messages are compressed with zlib-stream
+    pub compress: bool,
+    pub encoding: Encoding,
+    // Overrides by guild id
+    pub guilds: HashMap<String, GuildSettings>,
+}
+
+impl SettingsInitializer{
+
+    // Creates an initializer where no value is set
+    fn new() -> SettingsInitializer {
+        SettingsInitializer{
+            client:None,
+            guild:None,
+            secret:None,
+            token:None,
+            secret_file:None,
+            token_file:None,
+            intents:None,
+            delivery:None,
+            api_url:None,
+            api_version:None,
+            gateway_url:None,
+            shards:None,
+            log_level:None,
+            prefix:None,
+            status:None,
+            activity:None,
+            session_limit_file:None,
+            compress:None,
+            encoding:None,
+            guilds:HashMap::new(),
+        }
+    }
+
+    // Initializer with only the required values of loaded settings
+    fn required_from(settings: &Settings) -> SettingsInitializer {
+        let mut initializer = SettingsInitializer::new();
+        initializer.client = Some(settings.client.clone());
+        initializer.guild = Some(settings.guild.clone());
+        initializer.secret = Some(String::from(settings.secret.expose()));
+        initializer.token = Some(String::from(settings.token.expose()));
+        initializer
+    }
+
+    // Combines two configuration sources. Values set in self take precedence
+    // and values missing from self are taken from lower
+    fn merge(self, lower: SettingsInitializer) -> SettingsInitializer {
+        let mut guilds = lower.guilds;
+        guilds.extend(self.guilds);
+        SettingsInitializer{
+            client: self.client.or(lower.client),
+            guild: self.guild.or(lower.guild),
+            secret: self.secret.or(lower.secret),
+            token: self.token.or(lower.token),
+            secret_file: self.secret_file.or(lower.secret_file),
+            token_file: self.token_file.or(lower.token_file),
+            intents: self.intents.or(lower.intents),
+            delivery: self.delivery.or(lower.delivery),
+            api_url: self.api_url.or(lower.api_url),
+            api_version: self.api_version.or(lower.api_version),
+            gateway_url: self.gateway_url.or(lower.gateway_url),
+            shards: self.shards.or(lower.shards),
+            log_level: self.log_level.or(lower.log_level),
+            prefix: self.prefix.or(lower.prefix),
+            status: self.status.or(lower.status),
+            activity: self.activity.or(lower.activity),
+            session_limit_file: self.session_limit_file.or(lower.session_limit_file),
+            compress: self.compress.or(lower.compress),
+            encoding: self.encoding.or(lower.encoding),
+            guilds,
+        }
+    }
+
+    // Reads the secret and token from their files. Values given directly
+    // take precedence over files from the same source
+    fn read_secret_files(&mut self) -> Result<(), ConfigError> {
+        let files = vec![
+            ("secret", "secret_file", &mut self.secret, self.secret_file.as_ref()),
+            ("token", "token_file", &mut self.token, self.token_file.as_ref()),
+        ];
+        for (key, file_key, val, path) in files {
+            let path = match path {
+                Some(p) => p,
+                None => continue,
+            };
+            if val.is_some() {
+                warn!("Both {} and {} are set, {} is ignored", key, file_key, file_key);
+                continue;
+            }
+            match fs::read_to_string(path) {
+                // Files usually end with a newline that isn't part of the value
+                Ok(contents) => *val = Some(String::from(contents.trim())),
+                Err(e) => return Err(invalid_value(file_key, path.clone(), e.to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    // Value of a required setting, empty values count as missing
+    fn required(&self, key: &str) -> Option<&String> {
+        let val = match key {
+            "client" => &self.client,
+            "guild" => &self.guild,
+            "secret" => &self.secret,
+            "token" => &self.token,
+            _ => &None,
+        };
+        val.as_ref().filter(|v| !v.trim().is_empty())
+    }
+
+    // Convert a settings initializer object to a settings object. Fails with
+    // every missing required setting, or with the first invalid optional one.
+    // Optional settings that aren't given get their default value
+    fn finalize(self) -> Result<Settings, ConfigError> {
+        let missing: Vec<&'static str> = REQUIRED_KEYS.iter()
+            .filter(|key| self.required(key).is_none())
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            return Err(ConfigError::new(ConfigErrorKind::Missing(missing)));
+        }
+
+        Ok(Settings{
+            client: self.client.unwrap_or_default(),
+            guild: self.guild.unwrap_or_default(),
+            secret: Secret::new(self.secret.unwrap_or_default()),
+            token: Secret::new(self.token.unwrap_or_default()),
+            intents: parse_optional("intents", self.intents)?.unwrap_or_default(),
+            delivery: parse_optional("delivery", self.delivery)?.unwrap_or(Delivery::Gateway),
+            api_url: parse_url("api_url", self.api_url)?.unwrap_or_else(|| String::from(DEFAULT_API_URL)),
+            api_version: parse_optional("api_version", self.api_version)?.unwrap_or(DEFAULT_API_VERSION),
+            gateway_url: parse_url("gateway_url", self.gateway_url)?,
+            shards: parse_shards(self.shards)?,
+            log_level: parse_optional("log_level", self.log_level)?.unwrap_or(LevelFilter::Info),
+            prefix: self.prefix.unwrap_or_else(|| String::from(DEFAULT_PREFIX)),
+            status: parse_status(self.status)?,
+            activity: self.activity,
+            session_limit_file: self.session_limit_file
+                .unwrap_or_else(|| String::from(DEFAULT_SESSION_LIMIT_FILE)),
+            compress: parse_optional("compress", self.compress)?.unwrap_or(false),
+            encoding: parse_optional("encoding", self.encoding)?.unwrap_or(Encoding::Json),
+            guilds: self.guilds,
+        })
+    }
+}
+
+fn invalid_value(key: &'static str, value: String, reason: String) -> ConfigError {
+    ConfigError::new(ConfigErrorKind::InvalidValue { key, value, reason })
+}
+
+// Parses an optional setting, None if it is not given
+fn parse_optional<T>(key: &'static str, val: Option<String>) -> Result<Option<T>, ConfigError>
+        where T: FromStr, T::Err: fmt::Display {
+    match val {
+        None => Ok(None),
+        Some(val) => match T::from_str(val.trim()) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) => Err(invalid_value(key, val, e.to_string())),
+        },
+    }
+}
+
+fn parse_url(key: &'static str, val: Option<String>) -> Result<Option<String>, ConfigError> {
+    match val {
+        None => Ok(None),
+        Some(val) => match Url::parse(&val) {
+            Ok(_) => Ok(Some(val)),
+            Err(e) => Err(invalid_value(key, val, e.to_string())),
+        },
+    }
+}
+
+fn parse_status(val: Option<String>) -> Result<String, ConfigError> {
+    match val {
+        None => Ok(String::from(DEFAULT_STATUS)),
+        Some(val) if STATUSES.contains(&val.as_str()) => Ok(val),
+        Some(val) => Err(invalid_value("status", val, format!("must be one of {}", STATUSES.join(", ")))),
+    }
+}
+
+// Shards are decided by discord when not set or set to auto
+fn parse_shards(val: Option<String>) -> Result<Option<u32>, ConfigError> {
+    let val = val.filter(|v| v.trim() != "auto");
+    match parse_optional("shards", val.clone())? {
+        Some(0) => Err(invalid_value("shards", val.unwrap_or_default(), String::from("must be at least 1"))),
+        shards => Ok(shards),
+    }
+}
+
+impl FromStr for Delivery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Delivery, String> {
+        match s.trim() {
+            "gateway" => Ok(Delivery::Gateway),
+            "polling" => Ok(Delivery::Polling),
+            other => Err(format!("Unknown delivery \"{}\", expected gateway or polling", other)),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Encoding, String> {
+        match s.trim() {
+            "json" => Ok(Encoding::Json),
+            "etf" => Ok(Encoding::Etf),
+            other => Err(format!("Unknown encoding \"{}\", expected json or etf", other)),
+        }
+    }
+}
+
+impl Settings {
+    // Command prefix used in the guild, messages outside guilds use the default prefix
+    pub fn prefix_for(&self, guild: Option<&str>) -> &str {
+        guild.and_then(|id| self.guilds.get(id))
+            .and_then(|g| g.prefix.as_deref())
+            .unwrap_or(&self.prefix)
+    }
+}
+
+// Decides what to do with the given flags. Settings given as flags are set in
+// cli and config files are read into file
+fn handle_flags(
+        flags: &[Flag],
+        cli: &mut SettingsInitializer,
+        file: &mut SettingsInitializer,
+        interactive: &mut bool,
+        ) -> Result<(), Error> {
+    for f in flags {
+        match f {
+            Flag::ConfigFile(path) => {
+                parse_config_file(path, file)?;
+            },
+            Flag::Setting(key, val) => {
+                add_config_option(cli, key, val);
+            },
+            Flag::NonInteractive => {
+                *interactive = false;
+            },
+        }
+    }
+    Ok(())
+}
+
+// Maps the key to a variable in the settings struct and sets its value to val
+fn add_config_option(settings: &mut SettingsInitializer, key: &str, val: &str) {
+    match key {
+        "client" => {
+            settings.client = Some(String::from(val));
+        },
+        "guild" =>  {
+            settings.guild = Some(String::from(val));
+        },
+
+        "secret" =>  {
+            settings.secret = Some(String::from(val));
+        },
+
+        "token" =>  {
+            settings.token = Some(String::from(val));
+        },
+
+        "secret_file" =>  {
+            settings.secret_file = Some(String::from(val));
+        },
+
+        "token_file" =>  {
+            settings.token_file = Some(String::from(val));
+        },
+
+        "intents" =>  {
+            settings.intents = Some(String::from(val));
+        },
+
+        "delivery" =>  {
+            settings.delivery = Some(String::from(val));
+        },
+
+        "api_url" =>  {
+            settings.api_url = Some(String::from(val));
+        },
+
+        "api_version" =>  {
+            settings.api_version = Some(String::from(val));
+        },
+
+        "gateway_url" =>  {
+            settings.gateway_url = Some(String::from(val));
+        },
+
+        "shards" =>  {
+            settings.shards = Some(String::from(val));
+        },
+
+        "log_level" =>  {
+            settings.log_level = Some(String::from(val));
+        },
+
+        "prefix" =>  {
+            settings.prefix = Some(String::from(val));
+        },
+
+        "status" =>  {
+            settings.status = Some(String::from(val));
+        },
+
+        "activity" =>  {
+            settings.activity = Some(String::from(val));
+        },
+
+        "session_limit_file" =>  {
+            settings.session_limit_file = Some(String::from(val));
+        },
+
+        "compress" =>  {
+            settings.compress = Some(String::from(val));
+        },
+
+        "encoding" =>  {
+            settings.encoding = Some(String::from(val));
+        },
+
+        &_ => {}
+    };
+}
+
+// Sets the configurations that are given as environment variables
+fn handle_env_vars(settings: &mut SettingsInitializer) {
+    for key in CONFIG_KEYS.iter() {
+        let var = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+        if let Ok(val) = env::var(var) {
+            add_config_option(settings, key, &val);
+        }
+    }
+}
+
+// Parses file from path and sets the configuartions based on the file contents.
+// Files ending with .toml or containing [sections] are read as TOML and other
+// files with the legacy key=value format
+fn parse_config_file(path: &str, settings: &mut SettingsInitializer) -> Result<(), Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Could not read the config file {}", path);
+            return Err(Error::from(e));
+        },
+    };
+    let is_toml = path.ends_with(".toml") ||
+        contents.lines().any(|l| l.trim_start().starts_with('['));
+    if is_toml {
+        return toml_file::parse(path, &contents, settings);
+    }
+
+    let entries = match key_value::parse(&contents) {
+        Ok(e) => e,
+        Err(error) => return Err(Error::ConfigFile { path: String::from(path), error }),
+    };
+    for entry in entries {
+        if !CONFIG_KEYS.contains(&entry.key.as_str()) {
+            warn!("{} line {}: unknown key \"{}\" is ignored", path, entry.line, entry.key);
+            continue;
+        }
+        add_config_option(settings, &entry.key, &entry.value);
+    }
+    Ok(())
+}
+
+// Outputs prompt to stdout and reads line from stdin and returns as trimmed String
+fn prompt_value(prompt: &str) -> Result<String, Error> {
+    { 
+        // print immediately
+        use std::io::Write;
+        print!("{}", prompt);
+        io::stdout().flush()?;
+    }
+
+    // read input 
+    let mut inp = String::new();
+    io::stdin().read_line(&mut inp)?;
+
+    // return trimmed string
+    Ok(String::from(inp.trim()))
+}
+
+// Same as prompt_value but the input is not echoed to the terminal
+fn prompt_secret(prompt: &str) -> Result<String, Error> {
+    let inp = rpassword::prompt_password_stdout(prompt)?;
+    Ok(String::from(inp.trim()))
+}
+
+// Checks if certain values in the settings struct is set or not
+// Prompts selected values from stdin if not entered
+fn handle_missing_configvals(settings: &mut SettingsInitializer) -> Result<(), Error> {
+    // Vec defining prompts, value handles for when None and whether the
+    // value is secret
+    let prompt_handles: Vec<(&str, &mut Option<String>, bool)> = vec![
+        ("Client id: ", &mut settings.client, false),
+        ("Guild id: ", &mut settings.guild, false),
+        ("Secret: ", &mut settings.secret, true),
+        ("Token: ", &mut settings.token, true),
+    ];
+
+    for (prompt, val, secret) in prompt_handles {
+        *val = match val {
+            Some(_) => continue,
+            None if secret => Some(prompt_secret(prompt)?),
+            None => Some(prompt_value(prompt)?),
+        }
+    }
+    Ok(())
+}
+
+// Parses the command line arguments given to the binary
+pub fn parse_arguments() -> Result<Arguments, Error> {
+    cli::parse(env::args().skip(1))
+}
+
+// Creates and returns a settings object from where details about runtime specifics
+// can be fetched. Values are taken from, in order of precedence, command line
+// flags, environment variables, config files and lastly prompted from stdin.
+// Values are only prompted when stdin is a terminal and --non-interactive is not given
+pub fn get_settings(args: &Arguments) -> Result<Settings, Error> {
+    load_settings(args, None)
+}
+
+// Loads the settings from every source. When reloading nothing is prompted
+// and required values missing from every source, e.g. because they were
+// prompted at startup, are kept from the current settings
+fn load_settings(args: &Arguments, current: Option<&Settings>) -> Result<Settings, Error> {
+    let mut cli = SettingsInitializer::new();
+    let mut file = SettingsInitializer::new();
+    let mut env = SettingsInitializer::new();
+    let mut interactive = current.is_none() && io::stdin().is_terminal();
+
+    handle_flags(&args.flags, &mut cli, &mut file, &mut interactive)?;
+    handle_env_vars(&mut env);
+    for source in [&mut cli, &mut env, &mut file].iter_mut() {
+        source.read_secret_files()?;
+    }
+    let mut settings = cli.merge(env).merge(file);
+    if let Some(current) = current {
+        settings = settings.merge(SettingsInitializer::required_from(current));
+    }
+    // When not interactive finalize lists every missing value instead
+    if interactive {
+        handle_missing_configvals(&mut settings)?;
+    }
+    Ok(settings.finalize()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(values: &[(&str, &str)]) -> SettingsInitializer {
+        let mut settings = SettingsInitializer::new();
+        for (key, val) in values {
+            add_config_option(&mut settings, key, val);
+        }
+        settings
+    }
+
+    fn guild(prefix: &str) -> GuildSettings {
+        GuildSettings { prefix: Some(String::from(prefix)) }
+    }
+
+    #[test]
+    fn cli_takes_precedence_over_env_and_file() {
+        let cli = source(&[("client", "cli"), ("prefix", "?")]);
+        let env = source(&[("client", "env"), ("guild", "env"), ("prefix", "$")]);
+        let file = source(&[("client", "file"), ("guild", "file"), ("token", "file"), ("prefix", "%")]);
+        let settings = cli.merge(env).merge(file);
+        assert_eq!(settings.client.as_deref(), Some("cli"));
+        assert_eq!(settings.guild.as_deref(), Some("env"));
+        assert_eq!(settings.token.as_deref(), Some("file"));
+        assert_eq!(settings.prefix.as_deref(), Some("?"));
+        assert_eq!(settings.secret, None);
+    }
+
+    #[test]
+    fn env_takes_precedence_over_file() {
+        let env = source(&[("status", "idle"), ("shards", "2")]);
+        let file = source(&[("status", "dnd"), ("activity", "chess")]);
+        let settings = SettingsInitializer::new().merge(env).merge(file);
+        assert_eq!(settings.status.as_deref(), Some("idle"));
+        assert_eq!(settings.shards.as_deref(), Some("2"));
+        assert_eq!(settings.activity.as_deref(), Some("chess"));
+    }
+
+    #[test]
+    fn guilds_from_higher_sources_replace_lower_ones() {
+        let mut cli = SettingsInitializer::new();
+        cli.guilds.insert(String::from("1"), guild("cli"));
+        let mut file = SettingsInitializer::new();
+        file.guilds.insert(String::from("1"), guild("file"));
+        file.guilds.insert(String::from("2"), guild("file"));
+        let settings = cli.merge(SettingsInitializer::new()).merge(file);
+        assert_eq!(settings.guilds["1"].prefix.as_deref(), Some("cli"));
+        assert_eq!(settings.guilds["2"].prefix.as_deref(), Some("file"));
+    }
+
+    fn required() -> SettingsInitializer {
+        source(&[("client", "c"), ("guild", "g"), ("secret", "s"), ("token", "t")])
+    }
+
+    #[test]
+    fn finalize_lists_every_missing_required_key() {
+        let err = source(&[("guild", "g"), ("secret", "  "), ("token", "")]).finalize().unwrap_err();
+        assert_eq!(err, ConfigError::new(ConfigErrorKind::Missing(vec!["client", "secret", "token"])));
+        let err = SettingsInitializer::new().finalize().unwrap_err();
+        assert_eq!(err.kind, ConfigErrorKind::Missing(REQUIRED_KEYS.to_vec()));
+    }
+
+    #[test]
+    fn finalize_applies_defaults() {
+        let settings = required().finalize().unwrap();
+        assert_eq!(settings.client, "c");
+        assert_eq!(settings.token.expose(), "t");
+        assert_eq!(settings.intents, Intents::default());
+        assert_eq!(settings.delivery, Delivery::Gateway);
+        assert_eq!(settings.api_url, DEFAULT_API_URL);
+        assert_eq!(settings.api_version, DEFAULT_API_VERSION);
+        assert_eq!(settings.gateway_url, None);
+        assert_eq!(settings.shards, None);
+        assert_eq!(settings.log_level, LevelFilter::Info);
+        assert_eq!(settings.prefix, DEFAULT_PREFIX);
+        assert_eq!(settings.status, DEFAULT_STATUS);
+        assert_eq!(settings.activity, None);
+        assert_eq!(settings.session_limit_file, DEFAULT_SESSION_LIMIT_FILE);
+        assert!(!settings.compress);
+        assert_eq!(settings.encoding, Encoding::Json);
+    }
+
+    #[test]
+    fn finalize_parses_optional_values() {
+        let settings = source(&[("api_version", " 8 "), ("shards", "auto"), ("delivery", "polling")])
+            .merge(required())
+            .finalize()
+            .unwrap();
+        assert_eq!(settings.api_version, 8);
+        assert_eq!(settings.shards, None);
+        assert_eq!(settings.delivery, Delivery::Polling);
+    }
+
+    #[test]
+    fn finalize_rejects_invalid_optional_values() {
+        let invalid = [("api_version", "six"), ("shards", "0"), ("status", "away"), ("api_url", "not a url")];
+        for (key, val) in invalid.iter() {
+            let err = source(&[(key, val)]).merge(required()).finalize().unwrap_err();
+            match err.kind {
+                ConfigErrorKind::InvalidValue { key: k, value, .. } => {
+                    assert_eq!(k, *key);
+                    assert_eq!(value, *val);
+                },
+                kind => panic!("Expected invalid {}, got {:?}", key, kind),
+            }
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use signal_hook::consts::SIGHUP;
+
+use super::cli::Flag;
+use super::{load_settings, Arguments, Settings};
+use super::super::error::Error;
+
+// Settings shared between the bot and the config watcher. Only the
+// reloadable settings change after startup
+pub type SharedSettings = Arc<RwLock<Settings>>;
+
+// How often SIGHUP and the config files are checked
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+// Last modification time of every config file
+fn modified_times(paths: &[String]) -> Vec<Option<SystemTime>> {
+    paths.iter()
+        .map(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+// Settings that can't be changed without a restart and differ between current and new
+fn immutable_changes(current: &Settings, new: &Settings) -> Vec<&'static str> {
+    let changes = [
+        ("client", current.client != new.client),
+        ("guild", current.guild != new.guild),
+        ("secret", current.secret != new.secret),
+        ("token", current.token != new.token),
+        ("intents", current.intents != new.intents),
+        ("delivery", current.delivery != new.delivery),
+        ("api_url", current.api_url != new.api_url),
+        ("api_version", current.api_version != new.api_version),
+        ("gateway_url", current.gateway_url != new.gateway_url),
+        ("shards", current.shards != new.shards),
+        ("session_limit_file", current.session_limit_file != new.session_limit_file),
+        ("compress", current.compress != new.compress),
+        ("encoding", current.encoding != new.encoding),
+    ];
+    changes.iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(key, _)| *key)
+        .collect()
+}
+
+// Reads every config source again and applies the reloadable settings, which
+// are the prefix, guild overrides, log level and presence. Returns whether
+// the presence changed
+fn reload(args: &Arguments, shared: &SharedSettings) -> Result<bool, Error> {
+    let current = shared.read().expect("Settings lock poisoned").clone();
+    let new = load_settings(args, Some(&current))?;
+    for key in immutable_changes(&current, &new) {
+        warn!("Changing {} requires a restart, keeping the current value", key);
+    }
+
+    let presence_changed = current.status != new.status || current.activity != new.activity;
+    let mut settings = shared.write().expect("Settings lock poisoned");
+    settings.prefix = new.prefix;
+    settings.guilds = new.guilds;
+    settings.log_level = new.log_level;
+    settings.status = new.status;
+    settings.activity = new.activity;
+    log::set_max_level(settings.log_level);
+    Ok(presence_changed)
+}
+
+// Reloads the configuration in a separate thread whenever the process gets
+// SIGHUP or a config file given with -f is modified. on_presence is called
+// with the new settings when the presence changes. Configurations that fail
+// to load are logged and the current settings are kept
+pub fn watch<F>(args: &Arguments, shared: &SharedSettings, on_presence: F) -> Result<(), Error>
+        where F: Fn(&Settings) + Send + 'static {
+    let hangup = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGHUP, Arc::clone(&hangup))?;
+
+    let paths: Vec<String> = args.flags.iter()
+        .filter_map(|f| match f {
+            Flag::ConfigFile(path) => Some(path.clone()),
+            _ => None,
+        })
+        .collect();
+    let args = args.clone();
+    let shared = Arc::clone(shared);
+    thread::spawn(move || {
+        let mut modified = modified_times(&paths);
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let last_modified = modified;
+            modified = modified_times(&paths);
+            if hangup.swap(false, Ordering::Relaxed) {
+                info!("Received SIGHUP, reloading configuration");
+            } else if modified != last_modified {
+                info!("Config file modified, reloading configuration");
+            } else {
+                continue;
+            }
+
+            match reload(&args, &shared) {
+                Ok(presence_changed) => {
+                    info!("Configuration reloaded");
+                    if presence_changed {
+                        on_presence(&shared.read().expect("Settings lock poisoned"));
+                    }
+                },
+                Err(e) => warn!("Could not reload configuration, keeping the current settings: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
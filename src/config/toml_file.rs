@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Deserialize;
+
+use super::{add_config_option, ConfigError, ConfigErrorKind, GuildSettings, SettingsInitializer};
+use super::super::error::Error;
+
+// Layout of TOML config files, e.g.
+//
+// [discord]
+// client = "1234"
+// token = "..."
+//
+// [gateway]
+// intents = "GUILDS,GUILD_MESSAGES"
+//
+// [commands]
+// prefix = "!"
+//
+// [presence]
+// status = "online"
+// activity = "with rust"
+//
+// [guilds.5678]
+// prefix = "?"
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    discord: DiscordSection,
+    gateway: GatewaySection,
+    logging: LoggingSection,
+    commands: CommandsSection,
+    presence: PresenceSection,
+    // Overrides for single guilds in [guilds.<guild id>] sections
+    guilds: HashMap<String, GuildSettings>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct DiscordSection {
+    client: Option<Value>,
+    guild: Option<Value>,
+    secret: Option<String>,
+    token: Option<String>,
+    secret_file: Option<String>,
+    token_file: Option<String>,
+    api_url: Option<String>,
+    api_version: Option<Value>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct GatewaySection {
+    intents: Option<Value>,
+    shards: Option<Value>,
+    delivery: Option<String>,
+    url: Option<String>,
+    session_limit_file: Option<String>,
+    compress: Option<Value>,
+    encoding: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingSection {
+    level: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct CommandsSection {
+    prefix: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct PresenceSection {
+    status: Option<String>,
+    activity: Option<String>,
+}
+
+// Ids, numbers and flags may be written both as TOML strings and as their TOML type
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Value {
+    Text(String),
+    Number(u64),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+// Parses the contents of a TOML config file. Values are set through the same
+// config keys as the legacy format so both are validated the same way
+pub fn parse(path: &str, contents: &str, settings: &mut SettingsInitializer) -> Result<(), Error> {
+    let config: ConfigFile = match toml::from_str(contents) {
+        Ok(c) => c,
+        Err(e) => {
+            let error = ConfigError::new(ConfigErrorKind::Toml(e.to_string()));
+            return Err(Error::ConfigFile { path: String::from(path), error });
+        },
+    };
+
+    let to_string = |v: Option<Value>| v.map(|v| v.to_string());
+    let values = vec![
+        ("client", to_string(config.discord.client)),
+        ("guild", to_string(config.discord.guild)),
+        ("secret", config.discord.secret),
+        ("token", config.discord.token),
+        ("secret_file", config.discord.secret_file),
+        ("token_file", config.discord.token_file),
+        ("api_url", config.discord.api_url),
+        ("api_version", to_string(config.discord.api_version)),
+        ("intents", to_string(config.gateway.intents)),
+        ("shards", to_string(config.gateway.shards)),
+        ("delivery", config.gateway.delivery),
+        ("gateway_url", config.gateway.url),
+        ("session_limit_file", config.gateway.session_limit_file),
+        ("compress", to_string(config.gateway.compress)),
+        ("encoding", config.gateway.encoding),
+        ("log_level", config.logging.level),
+        ("prefix", config.commands.prefix),
+        ("status", config.presence.status),
+        ("activity", config.presence.activity),
+    ];
+    for (key, val) in values {
+        if let Some(val) = val {
+            add_config_option(settings, key, &val);
+        }
+    }
+    settings.guilds.extend(config.guilds);
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+// JSON error codes sent in the body of failed requests
+// https://discordapp.com/developers/docs/topics/opcodes-and-status-codes#json-json-error-codes
+pub const UNKNOWN_CHANNEL: u32 = 10003;
+pub const MISSING_ACCESS: u32 = 50001;
+pub const MISSING_PERMISSIONS: u32 = 50013;
+
+// Error body returned by the api for non 2xx responses, e.g.
+// {"code": 50035, "message": "Invalid Form Body", "errors": {...}}
+#[derive(Deserialize, Debug, Clone)]
+pub struct DiscordApiError {
+    // HTTP status of the response, not part of the body
+    #[serde(skip)]
+    pub status: u16,
+    // JSON error code, 0 when the body didn't contain one
+    pub code: u32,
+    pub message: String,
+    // Validation errors nested by field for invalid form bodies
+    #[serde(default)]
+    pub errors: Option<serde_json::Value>,
+}
+
+// Validation error of a single field, where the field is a path like "embed.fields.0.name"
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+impl DiscordApiError {
+    // Parses the error from a response body. Bodies that aren't discord errors,
+    // e.g. from a proxy, become an error with code 0 and the status as message
+    pub fn parse(status: StatusCode, body: &str) -> DiscordApiError {
+        let mut error = match serde_json::from_str::<DiscordApiError>(body) {
+            Ok(e) => e,
+            Err(_) => DiscordApiError {
+                status: 0,
+                code: 0,
+                message: String::from(status.canonical_reason().unwrap_or("Unknown error")),
+                errors: None,
+            },
+        };
+        error.status = status.as_u16();
+        error
+    }
+
+    // The bot isn't allowed to see or do what was requested
+    pub fn is_missing_access(&self) -> bool {
+        self.code == MISSING_ACCESS || self.code == MISSING_PERMISSIONS
+    }
+
+    // Flattens the nested errors object into a list of field errors
+    pub fn field_errors(&self) -> Vec<FieldError> {
+        let mut field_errors = Vec::new();
+        if let Some(errors) = &self.errors {
+            collect_field_errors(errors, "", &mut field_errors);
+        }
+        field_errors
+    }
+}
+
+// Errors are listed in "_errors" arrays at the path of the invalid field
+fn collect_field_errors(value: &serde_json::Value, path: &str, out: &mut Vec<FieldError>) {
+    let object = match value.as_object() {
+        Some(o) => o,
+        None => return,
+    };
+    for (key, value) in object {
+        if key == "_errors" {
+            for error in value.as_array().into_iter().flatten() {
+                let field = |name: &str| {
+                    String::from(error.get(name).and_then(|v| v.as_str()).unwrap_or_default())
+                };
+                out.push(FieldError {
+                    field: String::from(path),
+                    code: field("code"),
+                    message: field("message"),
+                });
+            }
+        } else if path.is_empty() {
+            collect_field_errors(value, key, out);
+        } else {
+            collect_field_errors(value, &format!("{}.{}", path, key), out);
+        }
+    }
+}
+
+impl fmt::Display for DiscordApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (code {}, status {})", self.message, self.code, self.status)?;
+        for error in self.field_errors() {
+            write!(f, "; {}: {} ({})", error.field, error.message, error.code)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DiscordApiError {}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use super::{Channel, Event, Guild, User};
+
+// State built up from the gateway events so that bot logic can look up guilds
+// and channels without asking the REST api
+#[derive(Debug, Default)]
+pub struct Cache {
+    // The bot's own user, set when the ready event is received
+    pub user: Option<User>,
+    pub guilds: HashMap<String, Guild>,
+    pub channels: HashMap<String, Channel>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::default()
+    }
+
+    pub fn guild(&self, id: &str) -> Option<&Guild> {
+        self.guilds.get(id)
+    }
+
+    pub fn channel(&self, id: &str) -> Option<&Channel> {
+        self.channels.get(id)
+    }
+
+    // Updates the cached state from an event. Must be called before the event
+    // is given to the handlers so that they see the updated state
+    pub fn update(&mut self, event: &Event) {
+        match event {
+            Event::Ready(ready) => {
+                self.user = Some(ready.user.clone());
+            },
+            Event::GuildCreate(guild) => {
+                for channel in &guild.channels {
+                    let mut channel = channel.clone();
+                    // Channels in guild create events don't contain the guild id
+                    channel.guild_id = Some(guild.id.clone());
+                    self.channels.insert(channel.id.clone(), channel);
+                }
+                self.guilds.insert(guild.id.clone(), guild.clone());
+            },
+            Event::GuildUpdate(guild) => {
+                // Updates don't contain channels and members so those are kept
+                let mut guild = guild.clone();
+                if let Some(old) = self.guilds.remove(&guild.id) {
+                    guild.channels = old.channels;
+                    guild.members = old.members;
+                    guild.member_count = guild.member_count.or(old.member_count);
+                }
+                self.guilds.insert(guild.id.clone(), guild);
+            },
+            Event::GuildDelete(guild) => {
+                self.guilds.remove(&guild.id);
+                self.channels.retain(|_, c| c.guild_id.as_ref() != Some(&guild.id));
+            },
+            Event::ChannelCreate(channel) | Event::ChannelUpdate(channel) => {
+                self.channels.insert(channel.id.clone(), channel.clone());
+            },
+            Event::ChannelDelete(channel) => {
+                self.channels.remove(&channel.id);
+            },
+            Event::GuildMemberAdd(member) => {
+                let guild_id = member.guild_id.as_ref();
+                if let Some(guild) = guild_id.and_then(|id| self.guilds.get_mut(id)) {
+                    guild.members.push(member.clone());
+                }
+            },
+            Event::GuildMemberRemove(removed) => {
+                if let Some(guild) = self.guilds.get_mut(&removed.guild_id) {
+                    guild.members.retain(|m| m.user.id != removed.user.id);
+                }
+            },
+            Event::MessageCreate(msg) => {
+                if let Some(channel) = self.channels.get_mut(&msg.channel_id) {
+                    channel.last_message_id = Some(msg.id.clone());
+                }
+            },
+            _ => {},
+        }
+    }
+}
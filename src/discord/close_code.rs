@@ -0,0 +1,131 @@
+use std::fmt;
+
+// Close codes the gateway closes the websocket with
+// https://discordapp.com/developers/docs/topics/opcodes-and-status-codes#gateway-close-event-codes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GatewayCloseCode {
+    NormalClosure,
+    GoingAway,
+    UnknownError,
+    UnknownOpcode,
+    DecodeError,
+    NotAuthenticated,
+    AuthenticationFailed,
+    AlreadyAuthenticated,
+    InvalidSeq,
+    RateLimited,
+    SessionTimedOut,
+    InvalidShard,
+    ShardingRequired,
+    InvalidApiVersion,
+    InvalidIntents,
+    DisallowedIntents,
+    // Any code not known to the bot, e.g. 1006 when the connection dropped
+    Other(u16),
+}
+
+// What a shard does after the gateway closed its connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CloseAction {
+    // Reconnect and resume the session
+    Resume,
+    // Reconnect with a new session as the old one was invalidated
+    Identify,
+    // Reconnecting would fail the same way again
+    Stop,
+}
+
+impl GatewayCloseCode {
+    pub fn from_code(code: u16) -> GatewayCloseCode {
+        match code {
+            1000 => GatewayCloseCode::NormalClosure,
+            1001 => GatewayCloseCode::GoingAway,
+            4000 => GatewayCloseCode::UnknownError,
+            4001 => GatewayCloseCode::UnknownOpcode,
+            4002 => GatewayCloseCode::DecodeError,
+            4003 => GatewayCloseCode::NotAuthenticated,
+            4004 => GatewayCloseCode::AuthenticationFailed,
+            4005 => GatewayCloseCode::AlreadyAuthenticated,
+            4007 => GatewayCloseCode::InvalidSeq,
+            4008 => GatewayCloseCode::RateLimited,
+            4009 => GatewayCloseCode::SessionTimedOut,
+            4010 => GatewayCloseCode::InvalidShard,
+            4011 => GatewayCloseCode::ShardingRequired,
+            4012 => GatewayCloseCode::InvalidApiVersion,
+            4013 => GatewayCloseCode::InvalidIntents,
+            4014 => GatewayCloseCode::DisallowedIntents,
+            code => GatewayCloseCode::Other(code),
+        }
+    }
+
+    pub fn code(self) -> u16 {
+        match self {
+            GatewayCloseCode::NormalClosure => 1000,
+            GatewayCloseCode::GoingAway => 1001,
+            GatewayCloseCode::UnknownError => 4000,
+            GatewayCloseCode::UnknownOpcode => 4001,
+            GatewayCloseCode::DecodeError => 4002,
+            GatewayCloseCode::NotAuthenticated => 4003,
+            GatewayCloseCode::AuthenticationFailed => 4004,
+            GatewayCloseCode::AlreadyAuthenticated => 4005,
+            GatewayCloseCode::InvalidSeq => 4007,
+            GatewayCloseCode::RateLimited => 4008,
+            GatewayCloseCode::SessionTimedOut => 4009,
+            GatewayCloseCode::InvalidShard => 4010,
+            GatewayCloseCode::ShardingRequired => 4011,
+            GatewayCloseCode::InvalidApiVersion => 4012,
+            GatewayCloseCode::InvalidIntents => 4013,
+            GatewayCloseCode::DisallowedIntents => 4014,
+            GatewayCloseCode::Other(code) => code,
+        }
+    }
+
+    // Normal closures and codes for an invalid session, sequence or
+    // authentication need a new session. Codes caused by the bot's token,
+    // shards, api version or intents stop the shard. Everything else, like
+    // unknown errors and dropped connections, is resumed
+    pub fn action(self) -> CloseAction {
+        match self {
+            GatewayCloseCode::AuthenticationFailed |
+            GatewayCloseCode::InvalidShard |
+            GatewayCloseCode::ShardingRequired |
+            GatewayCloseCode::InvalidApiVersion |
+            GatewayCloseCode::InvalidIntents |
+            GatewayCloseCode::DisallowedIntents => CloseAction::Stop,
+            GatewayCloseCode::NormalClosure |
+            GatewayCloseCode::GoingAway |
+            GatewayCloseCode::NotAuthenticated |
+            GatewayCloseCode::InvalidSeq |
+            GatewayCloseCode::SessionTimedOut => CloseAction::Identify,
+            _ => CloseAction::Resume,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            GatewayCloseCode::NormalClosure => "normal closure",
+            GatewayCloseCode::GoingAway => "going away",
+            GatewayCloseCode::UnknownError => "unknown error",
+            GatewayCloseCode::UnknownOpcode => "unknown opcode sent",
+            GatewayCloseCode::DecodeError => "invalid payload sent",
+            GatewayCloseCode::NotAuthenticated => "payload sent before identifying",
+            GatewayCloseCode::AuthenticationFailed => "authentication failed, check that the bot token is correct",
+            GatewayCloseCode::AlreadyAuthenticated => "identified more than once",
+            GatewayCloseCode::InvalidSeq => "invalid sequence number when resuming",
+            GatewayCloseCode::RateLimited => "payloads sent too quickly",
+            GatewayCloseCode::SessionTimedOut => "session timed out",
+            GatewayCloseCode::InvalidShard => "invalid shard",
+            GatewayCloseCode::ShardingRequired => "sharding required, the bot is in too many guilds for its shard count",
+            GatewayCloseCode::InvalidApiVersion => "invalid gateway version, check api_version",
+            GatewayCloseCode::InvalidIntents => "invalid intents",
+            GatewayCloseCode::DisallowedIntents => "disallowed intents, privileged intent not enabled in developer portal",
+            GatewayCloseCode::Other(_) => "unknown close code",
+        }
+    }
+}
+
+impl fmt::Display for GatewayCloseCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.description(), self.code())
+    }
+}
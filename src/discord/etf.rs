@@ -0,0 +1,423 @@
+use std::convert::TryFrom;
+use std::io::{self, Read};
+
+use flate2::read::ZlibDecoder;
+use serde_json::{Map, Number, Value};
+
+// Erlang external term format, used by the gateway with encoding=etf. Terms
+// are converted to and from json values so payloads are (de)serialized the
+// same way with both encodings
+// http://erlang.org/doc/apps/erts/erl_ext_dist.html
+const FORMAT_VERSION: u8 = 131;
+const NEW_FLOAT_EXT: u8 = 70;
+const COMPRESSED: u8 = 80;
+const SMALL_INTEGER_EXT: u8 = 97;
+const INTEGER_EXT: u8 = 98;
+const FLOAT_EXT: u8 = 99;
+const ATOM_EXT: u8 = 100;
+const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+const NIL_EXT: u8 = 106;
+const STRING_EXT: u8 = 107;
+const LIST_EXT: u8 = 108;
+const BINARY_EXT: u8 = 109;
+const SMALL_BIG_EXT: u8 = 110;
+const LARGE_BIG_EXT: u8 = 111;
+const SMALL_ATOM_EXT: u8 = 115;
+const MAP_EXT: u8 = 116;
+const ATOM_UTF8_EXT: u8 = 118;
+const SMALL_ATOM_UTF8_EXT: u8 = 119;
+
+// Terms nested deeper than this are rejected instead of overflowing the
+// stack, the same limit serde_json uses
+const MAX_DEPTH: u32 = 128;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Encodes a json value. Null and booleans become the atoms nil, true and
+// false, strings become binaries and objects become maps with binary keys
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = vec![FORMAT_VERSION];
+    encode_term(value, &mut out);
+    out
+}
+
+fn encode_atom(name: &str, out: &mut Vec<u8>) {
+    out.push(SMALL_ATOM_UTF8_EXT);
+    out.push(name.len() as u8);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn encode_binary(bytes: &[u8], out: &mut Vec<u8>) {
+    out.push(BINARY_EXT);
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn encode_integer(n: i128, out: &mut Vec<u8>) {
+    if (0..=255).contains(&n) {
+        out.push(SMALL_INTEGER_EXT);
+        out.push(n as u8);
+    } else if n >= i128::from(i32::MIN) && n <= i128::from(i32::MAX) {
+        out.push(INTEGER_EXT);
+        out.extend_from_slice(&(n as i32).to_be_bytes());
+    } else {
+        // Little endian digits of the absolute value
+        let mut abs = n.unsigned_abs();
+        let mut digits = Vec::new();
+        while abs > 0 {
+            digits.push(abs as u8);
+            abs >>= 8;
+        }
+        out.push(SMALL_BIG_EXT);
+        out.push(digits.len() as u8);
+        out.push(if n < 0 { 1 } else { 0 });
+        out.extend_from_slice(&digits);
+    }
+}
+
+fn encode_term(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => encode_atom("nil", out),
+        Value::Bool(b) => encode_atom(if *b { "true" } else { "false" }, out),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                encode_integer(i128::from(n), out);
+            } else if let Some(n) = n.as_i64() {
+                encode_integer(i128::from(n), out);
+            } else {
+                out.push(NEW_FLOAT_EXT);
+                out.extend_from_slice(&n.as_f64().unwrap_or_default().to_bits().to_be_bytes());
+            }
+        },
+        Value::String(s) => encode_binary(s.as_bytes(), out),
+        Value::Array(items) if items.is_empty() => out.push(NIL_EXT),
+        Value::Array(items) => {
+            out.push(LIST_EXT);
+            out.extend_from_slice(&(items.len() as u32).to_be_bytes());
+            for item in items {
+                encode_term(item, out);
+            }
+            out.push(NIL_EXT);
+        },
+        Value::Object(map) => {
+            out.push(MAP_EXT);
+            out.extend_from_slice(&(map.len() as u32).to_be_bytes());
+            for (key, value) in map {
+                encode_binary(key.as_bytes(), out);
+                encode_term(value, out);
+            }
+        },
+    }
+}
+
+// Decodes a term into a json value. Atoms and binaries become strings except
+// for nil, true and false, and tuples become arrays. Integers become numbers
+// the same as in json, so snowflakes stay integers and the models accept both
+// forms for ids
+pub fn decode(bytes: &[u8]) -> Result<Value, io::Error> {
+    let mut reader = bytes;
+    let version = read_u8(&mut reader)?;
+    if version != FORMAT_VERSION {
+        return Err(invalid_data(format!("Unknown term format version {}", version)));
+    }
+    decode_term(&mut reader, 0)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, io::Error> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16, io::Error> {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, io::Error> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_bytes<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, io::Error> {
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Term ended early"));
+    }
+    Ok(buf)
+}
+
+fn read_string<R: Read>(reader: &mut R, len: usize) -> Result<String, io::Error> {
+    let bytes = read_bytes(reader, len)?;
+    String::from_utf8(bytes).map_err(|e| invalid_data(e.to_string()))
+}
+
+fn atom(name: String) -> Value {
+    match name.as_str() {
+        "nil" => Value::Null,
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => Value::String(name),
+    }
+}
+
+fn decode_big<R: Read>(reader: &mut R, len: usize) -> Result<Value, io::Error> {
+    if len > 16 {
+        return Err(invalid_data(format!("Integer of {} bytes is too big", len)));
+    }
+    let negative = read_u8(reader)? != 0;
+    let digits = read_bytes(reader, len)?;
+    let n = digits.iter().rev().fold(0u128, |n, d| n << 8 | u128::from(*d));
+    // Like serde_json, integers outside of i64 and u64 become floats
+    let value = if negative {
+        match i128::try_from(n).ok().and_then(|n| i64::try_from(-n).ok()) {
+            Some(n) => Value::from(n),
+            None => float(-(n as f64))?,
+        }
+    } else {
+        match u64::try_from(n) {
+            Ok(n) => Value::from(n),
+            Err(_) => float(n as f64)?,
+        }
+    };
+    Ok(value)
+}
+
+fn decode_list<R: Read>(reader: &mut R, len: usize, depth: u32) -> Result<Vec<Value>, io::Error> {
+    (0..len).map(|_| decode_term(reader, depth)).collect()
+}
+
+// Depth is the number of terms the term is nested in
+fn decode_term<R: Read>(reader: &mut R, depth: u32) -> Result<Value, io::Error> {
+    if depth > MAX_DEPTH {
+        return Err(invalid_data(format!("Term is nested deeper than {} levels", MAX_DEPTH)));
+    }
+    let tag = read_u8(reader)?;
+    let value = match tag {
+        SMALL_INTEGER_EXT => Value::from(read_u8(reader)?),
+        INTEGER_EXT => Value::from(read_u32(reader)? as i32),
+        NEW_FLOAT_EXT => {
+            let mut buf = [0; 8];
+            reader.read_exact(&mut buf)?;
+            float(f64::from_bits(u64::from_be_bytes(buf)))?
+        },
+        FLOAT_EXT => {
+            let text = read_string(reader, 31)?;
+            let text = text.trim_end_matches('\0');
+            float(text.parse().map_err(|_| invalid_data(format!("Invalid float {}", text)))?)?
+        },
+        ATOM_EXT | ATOM_UTF8_EXT => {
+            let len = read_u16(reader)? as usize;
+            atom(read_string(reader, len)?)
+        },
+        SMALL_ATOM_EXT | SMALL_ATOM_UTF8_EXT => {
+            let len = read_u8(reader)? as usize;
+            atom(read_string(reader, len)?)
+        },
+        SMALL_TUPLE_EXT => {
+            let len = read_u8(reader)? as usize;
+            Value::Array(decode_list(reader, len, depth + 1)?)
+        },
+        LARGE_TUPLE_EXT => {
+            let len = read_u32(reader)? as usize;
+            Value::Array(decode_list(reader, len, depth + 1)?)
+        },
+        NIL_EXT => Value::Array(Vec::new()),
+        // Lists of small integers
+        STRING_EXT => {
+            let len = read_u16(reader)? as usize;
+            Value::Array(read_bytes(reader, len)?.into_iter().map(Value::from).collect())
+        },
+        LIST_EXT => {
+            let len = read_u32(reader)? as usize;
+            let items = decode_list(reader, len, depth + 1)?;
+            // Proper lists end with an empty list as tail
+            if decode_term(reader, depth + 1)? != Value::Array(Vec::new()) {
+                return Err(invalid_data(String::from("Improper lists are not supported")));
+            }
+            Value::Array(items)
+        },
+        BINARY_EXT => {
+            let len = read_u32(reader)? as usize;
+            Value::String(read_string(reader, len)?)
+        },
+        SMALL_BIG_EXT => {
+            let len = read_u8(reader)? as usize;
+            decode_big(reader, len)?
+        },
+        LARGE_BIG_EXT => {
+            let len = read_u32(reader)? as usize;
+            decode_big(reader, len)?
+        },
+        MAP_EXT => {
+            let len = read_u32(reader)? as usize;
+            let mut map = Map::new();
+            for _ in 0..len {
+                let key = match decode_term(reader, depth + 1)? {
+                    Value::String(key) => key,
+                    key => key.to_string(),
+                };
+                map.insert(key, decode_term(reader, depth + 1)?);
+            }
+            Value::Object(map)
+        },
+        COMPRESSED => {
+            // Inflates at most the declared size, which isn't trusted for allocating
+            let len = read_u32(reader)? as usize;
+            let mut decompressed = Vec::new();
+            ZlibDecoder::new(reader).take(len as u64).read_to_end(&mut decompressed)?;
+            if decompressed.len() != len {
+                return Err(invalid_data(format!("Compressed term is {} bytes instead of {}", decompressed.len(), len)));
+            }
+            decode_term(&mut &decompressed[..], depth + 1)?
+        },
+        tag => return Err(invalid_data(format!("Unsupported term tag {}", tag))),
+    };
+    Ok(value)
+}
+
+fn float(f: f64) -> Result<Value, io::Error> {
+    match Number::from_f64(f) {
+        Some(n) => Ok(Value::Number(n)),
+        None => Err(invalid_data(format!("Float {} is not a json number", f))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use serde_json::json;
+    use std::io::Write;
+
+    // Random json value that is at most depth levels deep
+    fn random_value(rng: &mut StdRng, depth: u32) -> Value {
+        let kinds = if depth == 0 { 6 } else { 8 };
+        match rng.gen_range(0, kinds) {
+            0 => Value::Null,
+            1 => Value::Bool(rng.gen()),
+            2 => Value::from(rng.gen::<u64>() >> rng.gen_range(0, 64)),
+            3 => Value::from(-(rng.gen::<i64>() >> rng.gen_range(1, 64)).abs()),
+            4 => Value::from(rng.gen_range(-1e12, 1e12)),
+            5 => {
+                let len = rng.gen_range(0, 12);
+                Value::String((0..len).map(|_| rng.gen::<char>()).collect())
+            },
+            6 => Value::Array((0..rng.gen_range(0, 5)).map(|_| random_value(rng, depth - 1)).collect()),
+            _ => Value::Object((0..rng.gen_range(0, 5))
+                .map(|i| (format!("key{}", i), random_value(rng, depth - 1)))
+                .collect()),
+        }
+    }
+
+    #[test]
+    fn random_values_round_trip() {
+        let mut rng = StdRng::seed_from_u64(24);
+        for _ in 0..2000 {
+            let value = random_value(&mut rng, 3);
+            assert_eq!(decode(&encode(&value)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn integers_keep_their_value() {
+        let values = json!([0, 255, 256, -1, 2147483647, 2147483648i64, -2147483648i64, -2147483649i64,
+                            12345678901234u64, 223456789012345678u64, u64::MAX, i64::MIN]);
+        assert_eq!(decode(&encode(&values)).unwrap(), values);
+        assert_eq!(decode(&encode(&json!({"big": 12345678901234u64}))).unwrap(), json!({"big": 12345678901234u64}));
+    }
+
+    #[test]
+    fn integer_encodings() {
+        assert_eq!(encode(&json!(7)), vec![131, SMALL_INTEGER_EXT, 7]);
+        assert_eq!(encode(&json!(-7)), vec![131, INTEGER_EXT, 255, 255, 255, 249]);
+        assert_eq!(encode(&json!(1u64 << 32)), vec![131, SMALL_BIG_EXT, 5, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(encode(&json!(-(1i64 << 32))), vec![131, SMALL_BIG_EXT, 5, 1, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn decodes_erlang_terms() {
+        // {op: 10, d: #{<<"heartbeat_interval">> => 41250}, s: nil, t: nil} with
+        // atom keys, like the gateway sends it
+        let mut bytes = vec![131, MAP_EXT, 0, 0, 0, 4];
+        bytes.extend_from_slice(&[ATOM_UTF8_EXT, 0, 2, b'o', b'p', SMALL_INTEGER_EXT, 10]);
+        bytes.extend_from_slice(&[SMALL_ATOM_EXT, 1, b'd', MAP_EXT, 0, 0, 0, 1]);
+        bytes.extend_from_slice(&[BINARY_EXT, 0, 0, 0, 18]);
+        bytes.extend_from_slice(b"heartbeat_interval");
+        bytes.extend_from_slice(&[INTEGER_EXT, 0, 0, 161, 34]);
+        bytes.extend_from_slice(&[ATOM_EXT, 0, 1, b's', SMALL_ATOM_UTF8_EXT, 3, b'n', b'i', b'l']);
+        bytes.extend_from_slice(&[SMALL_ATOM_UTF8_EXT, 1, b't', SMALL_ATOM_UTF8_EXT, 3, b'n', b'i', b'l']);
+        assert_eq!(decode(&bytes).unwrap(), json!({"op": 10, "d": {"heartbeat_interval": 41250}, "s": null, "t": null}));
+
+        // Tuples, strings of small integers and old style floats
+        let mut bytes = vec![131, SMALL_TUPLE_EXT, 3, STRING_EXT, 0, 2, 1, 2, SMALL_ATOM_EXT, 4];
+        bytes.extend_from_slice(b"true");
+        bytes.push(FLOAT_EXT);
+        let mut float = b"1.50000000000000000000e+00".to_vec();
+        float.resize(31, 0);
+        bytes.extend_from_slice(&float);
+        assert_eq!(decode(&bytes).unwrap(), json!([[1, 2], true, 1.5]));
+    }
+
+    #[test]
+    fn decodes_compressed_terms() {
+        let term = encode(&json!({"content": "a".repeat(100)}));
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&term[1..]).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut bytes = vec![131, COMPRESSED];
+        bytes.extend_from_slice(&(term.len() as u32 - 1).to_be_bytes());
+        bytes.extend_from_slice(&compressed);
+        assert_eq!(decode(&bytes).unwrap(), json!({"content": "a".repeat(100)}));
+
+        // Only the declared size is inflated, leaving a truncated term
+        let mut bytes = vec![131, COMPRESSED];
+        bytes.extend_from_slice(&10u32.to_be_bytes());
+        bytes.extend_from_slice(&compressed);
+        assert_eq!(decode(&bytes).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        // Less data than declared
+        let mut bytes = vec![131, COMPRESSED];
+        bytes.extend_from_slice(&1000u32.to_be_bytes());
+        bytes.extend_from_slice(&compressed);
+        assert_eq!(decode(&bytes).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn malformed_terms_are_errors() {
+        let malformed: [&[u8]; 6] = [
+            &[],
+            &[130, SMALL_INTEGER_EXT, 1],
+            &[131, BINARY_EXT, 0, 0, 0, 5, b'a'],
+            &[131, LIST_EXT, 0, 0, 0, 1, SMALL_INTEGER_EXT, 1, SMALL_INTEGER_EXT, 2],
+            &[131, LARGE_BIG_EXT, 0, 0, 1, 0, 0],
+            &[131, 200],
+        ];
+        for bytes in malformed.iter() {
+            assert!(decode(bytes).is_err(), "{:?}", bytes);
+        }
+
+        // Single element tuples nested around an integer
+        let nested = |depth: usize| {
+            let mut bytes = vec![FORMAT_VERSION];
+            for _ in 0..depth {
+                bytes.extend_from_slice(&[SMALL_TUPLE_EXT, 1]);
+            }
+            bytes.extend_from_slice(&[SMALL_INTEGER_EXT, 1]);
+            bytes
+        };
+        assert!(decode(&nested(MAX_DEPTH as usize)).is_ok());
+        assert_eq!(decode(&nested(MAX_DEPTH as usize + 1)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(decode(&nested(200_000)).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use super::{snowflake, Channel, Emoji, Guild, Member, Message, UnavailableGuild, User};
+use serde::{Deserialize, Serialize, Serializer};
+
+// Events dispatched by the gateway with opcode 0. The event name is sent in
+// the payload's t field and decides the format of the event data
+// https://discordapp.com/developers/docs/topics/gateway#commands-and-events-gateway-events
+#[derive(Debug)]
+pub enum Event {
+    Ready(Ready),
+    Resumed,
+    GuildCreate(Guild),
+    GuildUpdate(Guild),
+    GuildDelete(UnavailableGuild),
+    ChannelCreate(Channel),
+    ChannelUpdate(Channel),
+    ChannelDelete(Channel),
+    GuildMemberAdd(Member),
+    GuildMemberRemove(GuildMemberRemove),
+    MessageCreate(Message),
+    MessageUpdate(MessageUpdate),
+    MessageDelete(MessageDelete),
+    MessageReactionAdd(Reaction),
+    MessageReactionRemove(Reaction),
+    // Events that are not modelled keep their name and raw json data
+    Unknown { name: String, raw: serde_json::Value },
+}
+
+// Dispatched by the server when the identify has succeeded
+#[derive(Deserialize,Serialize,Debug)]
+pub struct Ready{
+    #[serde(rename = "v")]
+    pub version: u32,
+    pub user: User,
+    pub guilds: Vec<UnavailableGuild>,
+    // Used for resuming the session after a disconnect
+    pub session_id: String,
+    pub shard: Option<[u32; 2]>,
+}
+
+// Message updates only contain the id, channel and the fields that changed.
+// Embed only updates does not contain any content
+#[derive(Deserialize,Serialize,Debug)]
+pub struct MessageUpdate{
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub id: String,
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub channel_id: String,
+    #[serde(default, deserialize_with = "snowflake::option")]
+    pub guild_id: Option<String>,
+    pub content: Option<String>,
+    pub edited_timestamp: Option<String>,
+}
+
+#[derive(Deserialize,Serialize,Debug)]
+pub struct MessageDelete{
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub id: String,
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub channel_id: String,
+    #[serde(default, deserialize_with = "snowflake::option")]
+    pub guild_id: Option<String>,
+}
+
+#[derive(Deserialize,Serialize,Debug)]
+pub struct GuildMemberRemove{
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub guild_id: String,
+    pub user: User,
+}
+
+#[derive(Deserialize,Serialize,Debug)]
+pub struct Reaction{
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub user_id: String,
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub channel_id: String,
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub message_id: String,
+    #[serde(default, deserialize_with = "snowflake::option")]
+    pub guild_id: Option<String>,
+    pub emoji: Emoji,
+}
+
+impl Event {
+    // Creates the event from the event name and the raw event data. Unknown
+    // event names and known events whose data doesn't fit the model, e.g.
+    // after discord changed a field, are kept as Event::Unknown instead of
+    // failing. READY is the exception, without its session the shard can't
+    // become ready or resume, so it fails the payload instead
+    pub fn from_dispatch(name: &str, data: serde_json::Value) -> Result<Event, serde_json::Error> {
+        match Event::from_known(name, &data) {
+            Ok(Some(event)) => Ok(event),
+            Ok(None) => Ok(Event::Unknown { name: String::from(name), raw: data }),
+            Err(e) if name == "READY" => {
+                error!("Could not parse READY event, the session can't be resumed: {}", e);
+                Err(e)
+            },
+            Err(e) => {
+                warn!("Could not parse {} event, passing it on as unknown: {}", name, e);
+                Ok(Event::Unknown { name: String::from(name), raw: data })
+            },
+        }
+    }
+
+    // Parses the events the bot knows, None for other event names
+    fn from_known(name: &str, data: &serde_json::Value) -> Result<Option<Event>, serde_json::Error> {
+        let event = match name {
+            "READY" => Event::Ready(Deserialize::deserialize(data)?),
+            "RESUMED" => Event::Resumed,
+            "GUILD_CREATE" => Event::GuildCreate(Deserialize::deserialize(data)?),
+            "GUILD_UPDATE" => Event::GuildUpdate(Deserialize::deserialize(data)?),
+            "GUILD_DELETE" => Event::GuildDelete(Deserialize::deserialize(data)?),
+            "CHANNEL_CREATE" => Event::ChannelCreate(Deserialize::deserialize(data)?),
+            "CHANNEL_UPDATE" => Event::ChannelUpdate(Deserialize::deserialize(data)?),
+            "CHANNEL_DELETE" => Event::ChannelDelete(Deserialize::deserialize(data)?),
+            "GUILD_MEMBER_ADD" => Event::GuildMemberAdd(Deserialize::deserialize(data)?),
+            "GUILD_MEMBER_REMOVE" => Event::GuildMemberRemove(Deserialize::deserialize(data)?),
+            "MESSAGE_CREATE" => Event::MessageCreate(Deserialize::deserialize(data)?),
+            "MESSAGE_UPDATE" => Event::MessageUpdate(Deserialize::deserialize(data)?),
+            "MESSAGE_DELETE" => Event::MessageDelete(Deserialize::deserialize(data)?),
+            "MESSAGE_REACTION_ADD" => Event::MessageReactionAdd(Deserialize::deserialize(data)?),
+            "MESSAGE_REACTION_REMOVE" => Event::MessageReactionRemove(Deserialize::deserialize(data)?),
+            _ => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+
+    // Event name as sent in the payload's t field
+    pub fn name(&self) -> &str {
+        match self {
+            Event::Ready(_) => "READY",
+            Event::Resumed => "RESUMED",
+            Event::GuildCreate(_) => "GUILD_CREATE",
+            Event::GuildUpdate(_) => "GUILD_UPDATE",
+            Event::GuildDelete(_) => "GUILD_DELETE",
+            Event::ChannelCreate(_) => "CHANNEL_CREATE",
+            Event::ChannelUpdate(_) => "CHANNEL_UPDATE",
+            Event::ChannelDelete(_) => "CHANNEL_DELETE",
+            Event::GuildMemberAdd(_) => "GUILD_MEMBER_ADD",
+            Event::GuildMemberRemove(_) => "GUILD_MEMBER_REMOVE",
+            Event::MessageCreate(_) => "MESSAGE_CREATE",
+            Event::MessageUpdate(_) => "MESSAGE_UPDATE",
+            Event::MessageDelete(_) => "MESSAGE_DELETE",
+            Event::MessageReactionAdd(_) => "MESSAGE_REACTION_ADD",
+            Event::MessageReactionRemove(_) => "MESSAGE_REACTION_REMOVE",
+            Event::Unknown { name, .. } => name,
+        }
+    }
+}
+
+// Serializes only the event data, the name is sent separately in the payload
+impl Serialize for Event {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer, {
+            match self {
+                Event::Ready(d) => d.serialize(serializer),
+                Event::Resumed => serializer.serialize_none(),
+                Event::GuildCreate(d) | Event::GuildUpdate(d) => d.serialize(serializer),
+                Event::GuildDelete(d) => d.serialize(serializer),
+                Event::ChannelCreate(d) | Event::ChannelUpdate(d) | Event::ChannelDelete(d) => {
+                    d.serialize(serializer)
+                },
+                Event::GuildMemberAdd(d) => d.serialize(serializer),
+                Event::GuildMemberRemove(d) => d.serialize(serializer),
+                Event::MessageCreate(d) => d.serialize(serializer),
+                Event::MessageUpdate(d) => d.serialize(serializer),
+                Event::MessageDelete(d) => d.serialize(serializer),
+                Event::MessageReactionAdd(d) | Event::MessageReactionRemove(d) => {
+                    d.serialize(serializer)
+                },
+                Event::Unknown { raw, .. } => raw.serialize(serializer),
+            }
+    }
+}
@@ -1,302 +1,622 @@
-use std::{thread, time};
-
-use reqwest::{Client as HttpClient, Url};
-use super::API_BASE_URL;
-use serde::{de, Deserialize, Deserializer, Serialize};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use reqwest::Url;
+use super::{etf, Event, Http};
+use super::close_code::{CloseAction, GatewayCloseCode};
+use super::heartbeat::Heartbeat;
+use super::payload::*;
+use super::shard::{IdentifyQueue, Shard, ShardStatus};
+use super::zlib_stream::ZlibStream;
+use super::super::config::{Encoding, Settings, SharedSettings};
+use super::super::error::Error;
+use rand::Rng;
+use serde::Deserialize;
 use websocket::{
     ClientBuilder,
-    client::sync::Client as WsClientSync,
-    stream::sync::{
-        TlsStream as WsTlsStreamSync,
-        TcpStream as WsTcpStreamSync,
-    },
+    WebSocketError,
     client::r#async::{
         Client,
         ClientNew,
-        TlsStream,
-        TcpStream,
     },
+    stream::r#async::Stream as AsyncStream,
+    OwnedMessage,
     OwnedMessage::Text,
-    Message,
-    message,
-    futures::{Future, Stream, Sink},
+    message::CloseData,
+    futures::{Future, Poll, Stream, Sink, AsyncSink},
+    futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
     //--------------------------------------------------------------//
 };
 use tokio::runtime::Builder;
 use tokio::runtime::Runtime;
+use tokio::timer::Delay;
 use tokio::prelude::Async::{Ready, NotReady};
 
+type WsClient = Client<Box<dyn AsyncStream + Send>>;
+
+// Response of GET gateway/bot
 #[derive(Deserialize,Debug)]
-struct GatewayResponse {
+pub struct GatewayResponse {
     pub url: String,
     pub shards: u32,
     pub session_start_limit: GatewaySessionStartLimit,
 }
 
 #[derive(Deserialize,Debug)]
-struct GatewaySessionStartLimit {
+pub struct GatewaySessionStartLimit {
     pub total: u32,
     pub remaining: u32,
+    // Milliseconds until remaining is reset to total
     pub reset_after: u32,
+    // Number of shards that may identify at the same time
+    #[serde(default = "default_max_concurrency")]
+    pub max_concurrency: u32,
 }
 
-#[derive(Debug)]
-struct GatewayPayload{
-    pub op: i32, // Op-code
-    pub d: GatewayPayloadData, 
-    pub s: Option<i32>, // Sequence number
-    pub t: Option<String>,  // Event name
+fn default_max_concurrency() -> u32 {
+    1
 }
 
-#[derive(Serialize,Debug)]
-#[serde(untagged)]
-enum GatewayPayloadData {
-    Hello(HelloMsg),
-    _Empty,
-}
+// Presence updates for the connections of a shard. The receiver is shared so
+// that it is kept between connections
+pub type PresenceReceiver = Arc<Mutex<UnboundedReceiver<PresenceMsg>>>;
 
-// After gateway websocket connection is initiated a hello message is sent from server
-#[derive(Deserialize,Serialize,Debug)]
-struct HelloMsg{
-    // Client should send a heartbeat to server every <heartbeat_interval> milliseconds
-    heartbeat_interval: u64,
-}
+// Changes the presence of the running bot on every shard
+#[derive(Clone, Default)]
+pub struct PresenceSender(Arc<Mutex<Vec<UnboundedSender<PresenceMsg>>>>);
 
-// GatewayPayload contains both opcode and data where the format of data is dependant
-// of the opcode. This is the custom intermediate deserialization that first extracts
-// the data as raw json value and deserializes after exstracting the opcode
-impl<'de> Deserialize<'de> for GatewayPayload {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> 
-        where D: Deserializer<'de>, {
-            #[derive(Deserialize, Debug)]
-            struct Helper {
-                op: i32,
-                d: serde_json::Value,
-                s: Option<i32>,
-                t: Option<String>,
-            }
+impl PresenceSender {
+    pub fn new() -> PresenceSender {
+        PresenceSender::default()
+    }
 
-            let helper = Helper::deserialize(deserializer)?;
+    // Receiver for the presence updates of one shard
+    pub fn subscribe(&self) -> PresenceReceiver {
+        let (sender, receiver) = unbounded();
+        self.0.lock().expect("Presence lock poisoned").push(sender);
+        Arc::new(Mutex::new(receiver))
+    }
 
-            fn deserialize_payload_data<'a,T>(val: serde_json::Value) -> Result<T, serde_json::Error> 
-                    where for<'de> T: serde::Deserialize<'de>{
-                serde_json::from_value(val)            
-            }
+    // Sends the presence in the settings to every running shard. Does nothing
+    // when the bot doesn't use the gateway
+    pub fn update(&self, settings: &Settings) {
+        let mut senders = self.0.lock().expect("Presence lock poisoned");
+        senders.retain(|s| s.unbounded_send(PresenceMsg::new(settings)).is_ok());
+        if senders.is_empty() {
+            debug!("Gateway is not running, dropping presence update");
+        }
+    }
+}
 
-            let data = match helper.op {
-                10 => {
-                    match deserialize_payload_data::<HelloMsg>(helper.d) {
-                        Ok(m) => Ok(GatewayPayloadData::Hello(m)),
-                        Err(e) => Err(e),
-                    }
-                }
-                x => {
-                    panic!("Unknown discord gateway payload opcode {}", x);
-                },
-            };
+// Reasons for why a gateway connection ended
+#[derive(Debug)]
+enum Disconnect {
+    // No heartbeat ACK was received between two heartbeats
+    Zombied,
+    // The server asked the client to reconnect (opcode 7)
+    Reconnect,
+    // The session was invalidated (opcode 9)
+    InvalidSession { resumable: bool },
+    // The server closed the websocket, with the close code if one was sent
+    Closed(Option<GatewayCloseCode>),
+    // The websocket could not connect or failed without being closed
+    Error(WebSocketError),
+}
 
-            match data {
-                Ok(data) => {
-                    Ok(GatewayPayload{
-                        op: helper.op,
-                        d: data,
-                        s: helper.s,
-                        t: helper.t,
-                    })
-                },
-                Err(_) => Err(de::Error::custom("Could not deserialize gateway payload")),
-            }
-    }
+// Session state that is kept between connections so that a dropped connection
+// can be resumed and missed events replayed
+#[derive(Debug, Clone)]
+struct Session {
+    session_id: String,
+    // Last received sequence number
+    sequence: Option<i32>,
 }
 
+// A single websocket connection to the discord gateway. Polling the connection
+// handles incoming gateway messages and sends heartbeats until the connection ends
+struct GatewayConnection {
+    client: WsClient,
+    // Dispatched events are sent to the bot's event handlers through the shard
+    shard: Shard,
+    identify: IdentifyMsg,
+    encoding: Encoding,
+    // Decompresses binary messages when the connection uses zlib-stream
+    zlib_stream: Option<ZlibStream>,
+    session: Option<Session>,
+    heartbeat: Option<Heartbeat>,
+    heartbeat_timer: Option<Delay>,
+    sequence: Option<i32>,
+}
 
-fn send_get(client: &HttpClient, url: &Url) -> String {
-    let req = client.get(url.clone());
-                            
-    let mut resp = req.send().unwrap();
-    let body = resp.text().unwrap();
-    body
+fn send_get(http: &Http, url: &Url) -> Result<String, Error> {
+    let mut resp = http.get(url)?;
+    Ok(resp.text()?)
 }
 
-// General helping deserialization function. Used for reducing verbosity until 
-// a more robust option is created
-fn deserialize<'a, T: Deserialize<'a>>(body: &'a str) -> T {
-    let v : T = match serde_json::from_str(body) {
+// General helping deserialization function that logs the json that failed
+fn deserialize<'a, T: Deserialize<'a>>(body: &'a str) -> Result<T, Error> {
+    match serde_json::from_str(body) {
         Err(e) => {
-            println!("Something went wrong with deserializing json: {}", body);
-            panic!(e);
+            warn!("Something went wrong with deserializing json: {}", body);
+            Err(Error::from(e))
         },
-        Ok(a) => a,
-    };
-    v
+        Ok(a) => Ok(a),
+    }
 }
 
-// Creates and initiates connection for the websocket to the specified url and
-// panics if it is unable to connect. Sets version and encoding headers for the
-// connection
-fn create_websocket(url: &mut Url) -> WsClientSync<WsTlsStreamSync<WsTcpStreamSync>> {
-    url.set_query(Some("v=6&encoding=json"));
-    let mut ws_client = ClientBuilder::from_url(&url);
-    println!("Connecting to {}", url);
-    let ws_client = match ws_client.connect_secure(None) {
+// Parses a URL from a string
+fn create_url(url: &str) -> Result<Url, Error> {
+    match Url::parse(url) {
+        Ok(v) => Ok(v),
         Err(e) => {
-            println!("Error: {}", e);
-            panic!(format!("Could not connect websocket to {}", url));
-        }
-        Ok(c) => c,
-    };
-    ws_client
+            error!("Can't parse websocket url: {}", url);
+            Err(Error::from(e))
+        },
+    }
 }
 
-// Parses a URL from a string and panics if it can't be parsed
-fn create_url(url: &str) -> Url {
-    let url: Url = match Url::parse(url) {
-        Ok(v) => v,
-        Err(_) => {
-            panic!(format!("Can't parse websocket url: {}", url));
-        },
+// Creates the websocket connection future for the specified url. Both secure
+// (wss) and insecure (ws) urls are accepted. Sets version, encoding and
+// compression parameters for the connection
+fn create_websocket_async(
+        url :&mut Url,
+        version: u32,
+        encoding: Encoding,
+        compress: bool,
+        ) -> ClientNew<Box<dyn AsyncStream + Send>>{
+    let encoding = match encoding {
+        Encoding::Json => "json",
+        Encoding::Etf => "etf",
     };
-    url
+    let mut query = format!("v={}&encoding={}", version, encoding);
+    if compress {
+        query.push_str("&compress=zlib-stream");
+    }
+    url.set_query(Some(&query));
+    info!("Connecting to {}", url);
+    // create a Future of a client
+    let client_future: ClientNew<Box<dyn AsyncStream + Send>> =
+        ClientBuilder::from_url(url)
+            .async_connect(None);
+    client_future
 }
 
-// Blocks thread for param ms milliseconds
-fn thread_sleep(time_ms: u64){
-    let time_ms = time::Duration::from_millis(time_ms);
-    thread::sleep(time_ms);
+// Timer errors only happen when the runtime is shutting down
+fn timer_error(e: tokio::timer::Error) -> WebSocketError {
+    WebSocketError::IoError(io::Error::other(e))
 }
 
-// Handles the custom blocking eventloop for a discord websocket gateway
-fn gateway_eventloop_sync(
-        client: &mut WsClientSync<WsTlsStreamSync<WsTcpStreamSync>>, 
-        sleep_ms: u64
-        ){
-    loop {
-        thread_sleep(sleep_ms);
-        println!("Loop");
+impl GatewayConnection {
+    // Creates a connection that resumes the session if one is given and
+    // otherwise identifies as a new session
+    fn new(
+            client: WsClient,
+            shard: Shard,
+            identify: IdentifyMsg,
+            encoding: Encoding,
+            compress: bool,
+            session: Option<Session>,
+            ) -> GatewayConnection {
+        let sequence = session.as_ref().and_then(|s| s.sequence);
+        GatewayConnection {
+            client,
+            shard,
+            identify,
+            encoding,
+            zlib_stream: if compress { Some(ZlibStream::new()) } else { None },
+            session,
+            heartbeat: None,
+            heartbeat_timer: None,
+            sequence,
+        }
+    }
 
-        // Handle heartbeat   
-        let resp: message::OwnedMessage = match client.recv_message(){
-            Err(e) => {
-                println!("Error with receiving message from websocket: {}", e);
-                continue;
+    // Queues a payload to be sent over the websocket. The message is flushed
+    // when the connection is polled. ETF payloads are sent as binary messages
+    fn send_payload(&mut self, payload: &GatewayPayload) -> Result<(), WebSocketError> {
+        let message = match self.encoding {
+            Encoding::Json => OwnedMessage::Text(serde_json::to_string(payload)
+                .expect("Gateway payloads are always serializable")),
+            Encoding::Etf => OwnedMessage::Binary(etf::encode(&serde_json::to_value(payload)
+                .expect("Gateway payloads are always serializable"))),
+        };
+        if let AsyncSink::NotReady(_) = self.client.start_send(message)? {
+            warn!("Gateway send buffer full, dropped payload: {:?}", payload);
+        }
+        Ok(())
+    }
+
+    fn send_heartbeat(&mut self) -> Result<(), WebSocketError> {
+        let payload = GatewayPayload::heartbeat(self.sequence);
+        self.send_payload(&payload)
+    }
+
+    fn send_identify(&mut self) -> Result<(), WebSocketError> {
+        info!("Shard {} identifying with intents: {}", self.shard.id, self.identify.intents);
+        let payload = GatewayPayload::identify(self.identify.clone());
+        self.send_payload(&payload)
+    }
+
+    fn send_resume(&mut self, session_id: String) -> Result<(), WebSocketError> {
+        info!("Shard {} resuming session {} from sequence {:?}", self.shard.id, session_id, self.sequence);
+        let payload = GatewayPayload::resume(ResumeMsg {
+            token: self.identify.token.clone(),
+            session_id,
+            seq: self.sequence,
+        });
+        self.send_payload(&payload)
+    }
+
+    // Hello message containing the heartbeat interval that should be used
+    // Starts heartbeating with the received interval and resumes the previous
+    // session or identifies as a new one
+    fn handle_message_hello(&mut self, payload :&GatewayPayload) -> Result<(), WebSocketError> {
+        let data = match &payload.d {
+            GatewayPayloadData::Hello(msg) => msg,
+            _ => {
+                warn!("Unexpected data type in payload: {:?}", payload);
+                return Ok(());
             },
-            Ok(r) => r,
         };
-        println!("{:?}",resp);
+
+        let heartbeat = Heartbeat::new(data.heartbeat_interval);
+        debug!("Heartbeat_interval: {:?}", heartbeat.interval());
+        self.heartbeat_timer = Some(Delay::new(heartbeat.next_beat()));
+        self.heartbeat = Some(heartbeat);
+        match self.session.as_ref() {
+            Some(session) => {
+                let session_id = session.session_id.clone();
+                self.send_resume(session_id)
+            },
+            None => self.send_identify(),
+        }
     }
-}
 
-fn create_websocket_async(url :&mut Url) -> ClientNew<TlsStream<TcpStream>>{
-    url.set_query(Some("v=6&encoding=json"));
-    // create a Future of a client
-    let client_future: ClientNew<TlsStream<TcpStream>> =
-        ClientBuilder::from_url(url)
-            .async_connect_secure(None);
-    client_future
-}
+    // The server may request a heartbeat at any time, which should be sent immediately
+    fn handle_message_heartbeat(&mut self) -> Result<(), WebSocketError> {
+        self.send_heartbeat()?;
+        if let Some(heartbeat) = self.heartbeat.as_mut() {
+            heartbeat.sent(Instant::now());
+        }
+        Ok(())
+    }
 
-// Hello message containing the heartbeat interval that should be used
-// This function extracts that value and returns it
-fn handle_message_hello(payload :&GatewayPayload, client: &mut Client<TlsStream<TcpStream>>)
-    -> Option<u64> {
-    let data = match &payload.d {
-        GatewayPayloadData::Hello(msg) => msg,
-        _ => {
-            println!("Unexpected data type in payload: {:?}", payload);
-            return None;
-        },
-    };
+    fn handle_message_heartbeat_ack(&mut self) {
+        if let Some(heartbeat) = self.heartbeat.as_mut() {
+            heartbeat.acknowledge(Instant::now());
+            debug!("Heartbeat ACK, latency: {:?}", heartbeat.latency());
+        }
+    }
 
-    let heartbeat_interval = data.heartbeat_interval;
-    Some(heartbeat_interval)
-}
+    // Dispatched events (opcode 0) with the event name in the payload
+    fn handle_message_dispatch(&mut self, payload: GatewayPayload) {
+        let event = match payload.d {
+            GatewayPayloadData::Dispatch(event) => event,
+            _ => {
+                warn!("Unexpected data type in payload: {:?}", payload);
+                return;
+            },
+        };
+        match &event {
+            Event::Ready(ready) => {
+                info!("Ready as {}#{} (bot: {}, id: {}) in {} guilds, session: {}, gateway v{}, shard: {:?}",
+                         ready.user.username, ready.user.discriminator, ready.user.bot, ready.user.id,
+                         ready.guilds.len(), ready.session_id, ready.version, ready.shard);
+                self.session = Some(Session {
+                    session_id: ready.session_id.clone(),
+                    sequence: self.sequence,
+                });
+                self.shard.set_status(ShardStatus::Ready);
+            },
+            Event::Resumed => {
+                info!("Shard {} resumed session, missed events have been replayed", self.shard.id);
+                self.shard.set_status(ShardStatus::Ready);
+            },
+            _ => {},
+        }
 
-fn handle_message_text(client: &mut Client<TlsStream<TcpStream>>, message :&str){
-    let payload: GatewayPayload = deserialize(message);
-    match payload.op {
-        10 => {
-            match handle_message_hello(&payload, client) {
-                Some(heartbeat_interval) => {
-                    println!("Heartbeat_interval: {}", heartbeat_interval);
-                },
-                None => {},
-            };
-        },
-        unhandled_code => {
-            println!("Unhandled opcode in gateway message: {:?}", unhandled_code);
-        },
-    };
-}
+        if self.shard.events.send((self.shard.id, event)).is_err() {
+            warn!("Event handlers have stopped, dropping gateway event");
+        }
+    }
 
-fn poll_messages(client: &mut Client<TlsStream<TcpStream>>){
-    //If error occurs too many times then panic
-    let mut errors = 0; 
-    let errors_max = 5;
-    loop {
-        let res = client.poll();
-        match res {
-            Ok(Ready(x)) => {
-                match x {
-                    Some(Text(msg)) => {
-                        handle_message_text(client, &msg);
-                    },
-                    Some(_) => {println!("Non text gateway message received")},
-                    None => {println!("Found none when gateway message should be ready");},
-                };
+    // Decodes a binary message, which is either ETF or decompressed json
+    fn decode_binary(&self, message: &[u8]) -> Result<GatewayPayload, Error> {
+        match self.encoding {
+            Encoding::Json => match std::str::from_utf8(message) {
+                Ok(text) => deserialize(text),
+                Err(e) => Err(Error::from(io::Error::new(io::ErrorKind::InvalidData, e))),
             },
-            Ok(NotReady) => {
-                break;
+            Encoding::Etf => {
+                let value = etf::decode(message)?;
+                match serde_json::from_value(value.clone()) {
+                    Ok(payload) => Ok(payload),
+                    Err(e) => {
+                        warn!("Something went wrong with deserializing etf: {}", value);
+                        Err(Error::from(e))
+                    },
+                }
             },
+        }
+    }
+
+    // Returns a disconnect reason if the message ends the connection. Malformed
+    // messages are skipped
+    fn handle_message(&mut self, payload: Result<GatewayPayload, Error>) -> Result<Option<Disconnect>, WebSocketError> {
+        let payload = match payload {
+            Ok(payload) => payload,
             Err(e) => {
-                errors += 1;
-                println!("An error occurred: {:?}", e);
-                if errors >= errors_max {
-                    panic!("Too many errors when polling messages.. last known error: {:?}", e);
-                }else{
-                    continue;
-                }
+                warn!("Skipping malformed gateway message: {}", e);
+                return Ok(None);
+            },
+        };
+        if payload.s.is_some() {
+            self.sequence = payload.s;
+        }
+        match payload.op {
+            OP_DISPATCH => self.handle_message_dispatch(payload),
+            OP_HEARTBEAT => self.handle_message_heartbeat()?,
+            OP_RECONNECT => return Ok(Some(Disconnect::Reconnect)),
+            OP_INVALID_SESSION => {
+                let resumable = match payload.d {
+                    GatewayPayloadData::InvalidSession(resumable) => resumable,
+                    _ => false,
+                };
+                return Ok(Some(Disconnect::InvalidSession { resumable }));
+            },
+            OP_HELLO => self.handle_message_hello(&payload)?,
+            OP_HEARTBEAT_ACK => self.handle_message_heartbeat_ack(),
+            unhandled_code => {
+                debug!("Unhandled opcode in gateway message: {:?}", unhandled_code);
             },
         };
+        Ok(None)
     }
-}
 
-fn setup_discord_gateway_async(gateway_url :&mut Url){
-    let client_future = create_websocket_async(gateway_url);
-    let gateway_message_handler = client_future.map(|(mut client,_)| { 
+    // Handles all messages that are ready on the websocket. Returns a
+    // disconnect reason if the connection has ended
+    fn poll_messages(&mut self) -> Result<Option<Disconnect>, WebSocketError> {
         loop {
-            let time: u64 = 500;
-            println!("Sleeping for {} ms to try to poll for messages", time);
-            thread_sleep(time);
-            poll_messages(&mut client);
+            match self.client.poll()? {
+                Ready(Some(Text(msg))) => {
+                    if let Some(disconnect) = self.handle_message(deserialize(&msg))? {
+                        return Ok(Some(disconnect));
+                    }
+                },
+                Ready(Some(OwnedMessage::Close(data))) => {
+                    let code = data.as_ref().map(|d| GatewayCloseCode::from_code(d.status_code));
+                    match (&code, &data) {
+                        (Some(code), Some(data)) => info!("Shard {} closed by gateway: {}, reason: {:?}",
+                                                          self.shard.id, code, data.reason),
+                        _ => info!("Shard {} closed by gateway without a close code", self.shard.id),
+                    }
+                    return Ok(Some(Disconnect::Closed(code)));
+                },
+                Ready(Some(OwnedMessage::Binary(data))) => {
+                    let message = match self.zlib_stream.as_mut() {
+                        Some(zlib_stream) => zlib_stream.push(&data)?,
+                        None => Some(data),
+                    };
+                    if let Some(msg) = message {
+                        let payload = self.decode_binary(&msg);
+                        if let Some(disconnect) = self.handle_message(payload)? {
+                            return Ok(Some(disconnect));
+                        }
+                    }
+                },
+                Ready(Some(_)) => {debug!("Non text gateway message received")},
+                Ready(None) => {
+                    info!("Gateway websocket stream ended");
+                    return Ok(Some(Disconnect::Closed(None)));
+                },
+                NotReady => {
+                    return Ok(None);
+                },
+            };
         }
-    });
+    }
+
+    // Sends heartbeats whenever the heartbeat timer fires. Returns a
+    // disconnect reason if the previous heartbeat was never acknowledged
+    fn poll_heartbeat(&mut self) -> Result<Option<Disconnect>, WebSocketError> {
+        loop {
+            let timer = match self.heartbeat_timer.as_mut() {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            if let NotReady = timer.poll().map_err(timer_error)? {
+                return Ok(None);
+            }
+
+            let heartbeat = self.heartbeat.as_mut()
+                .expect("Heartbeat timer is only set together with heartbeat");
+            if heartbeat.is_zombied() {
+                warn!("No heartbeat ACK received since last heartbeat, connection is zombied");
+                return Ok(Some(Disconnect::Zombied));
+            }
+            let now = Instant::now();
+            heartbeat.beat(now);
+            let next_beat = heartbeat.next_beat();
+            timer.reset(next_beat);
+            self.send_heartbeat()?;
+        }
+    }
+
+    // Sends presence updates from the config watcher. Updates received before
+    // hello replace the presence that is sent in identify
+    fn poll_presence(&mut self) -> Result<(), WebSocketError> {
+        loop {
+            let presence = {
+                let mut updates = self.shard.presence_updates.lock().expect("Presence lock poisoned");
+                match updates.poll() {
+                    Ok(Ready(Some(presence))) => presence,
+                    _ => return Ok(()),
+                }
+            };
+            if self.heartbeat.is_none() {
+                self.identify.presence = presence;
+                continue;
+            }
+            info!("Updating presence to {}", presence.status);
+            let payload = GatewayPayload::new(GatewayPayloadData::PresenceUpdate(presence));
+            self.send_payload(&payload)?;
+        }
+    }
+
+    // Closes the connection with a non 1000 close code so that the session
+    // stays valid for resuming
+    fn close(&mut self, reason: &str) -> Result<(), WebSocketError> {
+        let close = OwnedMessage::Close(Some(CloseData::new(4000, String::from(reason))));
+        self.client.start_send(close)?;
+        self.client.poll_complete()?;
+        Ok(())
+    }
 
-    let mut runtime = Builder::new().build().unwrap();
-    let res = runtime.block_on(gateway_message_handler);
-    //println!("Res {:?}", res);
+    fn poll_connection(&mut self) -> Poll<Disconnect, WebSocketError> {
+        self.poll_presence()?;
+        if let Some(disconnect) = self.poll_messages()? {
+            if let Disconnect::Closed(_) = disconnect {
+                return Ok(Ready(disconnect));
+            }
+            self.close("Reconnecting")?;
+            return Ok(Ready(disconnect));
+        }
+        if let Some(disconnect) = self.poll_heartbeat()? {
+            self.close("Zombied connection")?;
+            return Ok(Ready(disconnect));
+        }
+        self.client.poll_complete()?;
+        Ok(NotReady)
+    }
+
+    // Session with the last received sequence number, for resuming on a new connection
+    fn take_session(&mut self) -> Option<Session> {
+        let sequence = self.sequence;
+        self.session.take().map(|mut s| {
+            s.sequence = sequence;
+            s
+        })
+    }
 }
 
-pub fn initiate_gateway(client: &HttpClient) -> bool{
-    let gateway_url = create_url(&format!("{}gateway/bot", API_BASE_URL));
-    let body = send_get(client, &gateway_url);
-    let v : GatewayResponse = deserialize(&body);
-    let mut gateway_url = create_url(&v.url);
+// Resolves to why the connection ended together with the session that can
+// be resumed. Websocket errors also end the connection instead of failing
+// the future so that the session is not lost
+impl Future for GatewayConnection {
+    type Item = (Disconnect, Option<Session>);
+    type Error = WebSocketError;
+
+    fn poll(&mut self) -> Poll<(Disconnect, Option<Session>), WebSocketError> {
+        let disconnect = match self.poll_connection() {
+            Ok(NotReady) => return Ok(NotReady),
+            Ok(Ready(disconnect)) => disconnect,
+            Err(e) => Disconnect::Error(e),
+        };
+        Ok(Ready((disconnect, self.take_session())))
+    }
+}
 
-    ///////////////  ASYNC   ////////////////////////////////////
-    setup_discord_gateway_async(&mut gateway_url);
+// Connects to the gateway and runs the connection until it ends. Resumes the
+// session if one is given
+fn setup_discord_gateway_async(
+        runtime: &mut Runtime,
+        gateway_url :&mut Url,
+        settings: &Settings,
+        shard: Shard,
+        identify: IdentifyMsg,
+        session: Option<Session>,
+        ) -> (Disconnect, Option<Session>) {
+    let client_future = create_websocket_async(
+        gateway_url, settings.api_version, settings.encoding, settings.compress);
+    let connection_session = session.clone();
+    let (encoding, compress) = (settings.encoding, settings.compress);
+    let connection = client_future.and_then(move |(client, _)| {
+        GatewayConnection::new(client, shard, identify, encoding, compress, connection_session)
+    });
 
-    let a = true;
-    if a {
-        return true;
+    match runtime.block_on(connection) {
+        Ok(res) => res,
+        Err(e) => (Disconnect::Error(e), session),
     }
+}
 
-    //////////////////////////////////////////////////////////////
+// Blocks thread for a random time between 1 and 5 seconds, which is the wait
+// required before identifying after an invalid session
+fn random_backoff() {
+    let time_ms = rand::thread_rng().gen_range(1000, 5000);
+    info!("Waiting {} ms before reconnecting", time_ms);
+    thread::sleep(Duration::from_millis(time_ms));
+}
 
-    //TODO add explicit version and encoding parameters to request
+// Asks the api for the gateway url, the recommended number of shards and
+// how many more sessions may be started
+pub fn gateway_info(http: &Http) -> Result<GatewayResponse, Error> {
+    let body = send_get(http, &http.url("gateway/bot")?)?;
+    deserialize(&body)
+}
 
-    let mut ws = create_websocket(&mut create_url(&v.url));
-    gateway_eventloop_sync(&mut ws, 500);
-    
-    println!("----\n{:?}\n------", v);
-    true
+// Connects a shard to the gateway and keeps reconnecting whenever the
+// connection ends. Dropped connections are resumed and invalidated sessions
+// identify anew once the identify queue allows it. Dispatched events are sent
+// to the shard's events channel and presence updates received by the shard are
+// sent to the gateway. Only returns when the gateway closes the connection with
+// a fatal close code
+pub fn initiate_gateway(
+        shared: &SharedSettings,
+        shard: &Shard,
+        url: &str,
+        queue: &IdentifyQueue,
+        ) -> Result<(), Error> {
+    let settings = shared.read().expect("Settings lock poisoned").clone();
+    let mut identify = IdentifyMsg::new(&settings);
+    identify.shard = Some([shard.id, shard.count]);
+    let mut runtime = Builder::new().build()?;
+    let mut session: Option<Session> = None;
+    loop {
+        // New sessions are identified with the presence of the reloaded settings
+        identify.presence = PresenceMsg::new(&shared.read().expect("Settings lock poisoned"));
+        if session.is_none() {
+            shard.set_status(ShardStatus::Queued);
+            queue.wait(shard.id);
+        }
+        shard.set_status(ShardStatus::Connecting);
+        let mut gateway_url = create_url(url)?;
+        let (disconnect, last_session) = setup_discord_gateway_async(
+            &mut runtime, &mut gateway_url, &settings, shard.clone(),
+            identify.clone(), session.take());
+        shard.set_status(ShardStatus::Reconnecting);
+        session = last_session;
+
+        match disconnect {
+            Disconnect::Zombied | Disconnect::Reconnect => {},
+            Disconnect::InvalidSession { resumable } => {
+                if !resumable {
+                    session = None;
+                }
+                random_backoff();
+            },
+            Disconnect::Closed(None) => {},
+            Disconnect::Closed(Some(code)) => match code.action() {
+                CloseAction::Resume => {},
+                CloseAction::Identify => {
+                    info!("Session can't be resumed after close code {}", code.code());
+                    session = None;
+                    random_backoff();
+                },
+                CloseAction::Stop => {
+                    if code == GatewayCloseCode::DisallowedIntents {
+                        error!("Privileged intents requested: {}", settings.intents.privileged().join(", "));
+                    }
+                    return Err(Error::Gateway(code));
+                },
+            },
+            Disconnect::Error(e) => {
+                warn!("Gateway connection error: {:?}", e);
+                random_backoff();
+            },
+        }
+        info!("Shard {} reconnecting to gateway", shard.id);
+    }
 }
@@ -0,0 +1,90 @@
+use super::cache::Cache;
+use super::shard::Shards;
+use super::events::{GuildMemberRemove, MessageDelete, MessageUpdate, Reaction, Ready};
+use super::{Channel, Event, Guild, Http, Member, Message, UnavailableGuild};
+
+// Bot logic is implemented as event handlers. Every method has a default
+// implementation that does nothing so a handler only implements the events
+// it is interested in
+pub trait EventHandler {
+    fn on_ready(&self, _ctx: &Context, _ready: &Ready) {}
+    fn on_resumed(&self, _ctx: &Context) {}
+    fn on_guild_create(&self, _ctx: &Context, _guild: &Guild) {}
+    fn on_guild_update(&self, _ctx: &Context, _guild: &Guild) {}
+    fn on_guild_delete(&self, _ctx: &Context, _guild: &UnavailableGuild) {}
+    fn on_channel_create(&self, _ctx: &Context, _channel: &Channel) {}
+    fn on_channel_update(&self, _ctx: &Context, _channel: &Channel) {}
+    fn on_channel_delete(&self, _ctx: &Context, _channel: &Channel) {}
+    fn on_member_join(&self, _ctx: &Context, _member: &Member) {}
+    fn on_member_leave(&self, _ctx: &Context, _removed: &GuildMemberRemove) {}
+    fn on_message(&self, _ctx: &Context, _msg: &Message) {}
+    fn on_message_update(&self, _ctx: &Context, _update: &MessageUpdate) {}
+    fn on_message_delete(&self, _ctx: &Context, _deleted: &MessageDelete) {}
+    fn on_reaction_add(&self, _ctx: &Context, _reaction: &Reaction) {}
+    fn on_reaction_remove(&self, _ctx: &Context, _reaction: &Reaction) {}
+    // Events that are not modelled with their name and raw json data
+    fn on_unknown(&self, _ctx: &Context, _name: &str, _raw: &serde_json::Value) {}
+}
+
+// Given to the handlers for every event
+pub struct Context {
+    // Rate limited REST client with the bot authorization set
+    pub http: Http,
+    pub cache: Cache,
+    // Shard that received the event, 0 when messages are polled
+    pub shard: u32,
+    // Status of every shard of the bot
+    pub shards: Shards,
+}
+
+// Routes gateway events to the registered handlers
+pub struct Dispatcher {
+    handlers: Vec<Box<dyn EventHandler>>,
+    context: Context,
+}
+
+impl Dispatcher {
+    pub fn new(http: Http, shards: Shards) -> Dispatcher {
+        Dispatcher {
+            handlers: Vec::new(),
+            context: Context {
+                http,
+                cache: Cache::new(),
+                shard: 0,
+                shards,
+            },
+        }
+    }
+
+    pub fn register(&mut self, handler: Box<dyn EventHandler>) {
+        self.handlers.push(handler);
+    }
+
+    // Updates the cache with the event and calls every handler in the order
+    // they were registered, with the shard that received the event
+    pub fn dispatch(&mut self, shard: u32, event: &Event) {
+        self.context.cache.update(event);
+        self.context.shard = shard;
+        let ctx = &self.context;
+        for handler in &self.handlers {
+            match event {
+                Event::Ready(ready) => handler.on_ready(ctx, ready),
+                Event::Resumed => handler.on_resumed(ctx),
+                Event::GuildCreate(guild) => handler.on_guild_create(ctx, guild),
+                Event::GuildUpdate(guild) => handler.on_guild_update(ctx, guild),
+                Event::GuildDelete(guild) => handler.on_guild_delete(ctx, guild),
+                Event::ChannelCreate(channel) => handler.on_channel_create(ctx, channel),
+                Event::ChannelUpdate(channel) => handler.on_channel_update(ctx, channel),
+                Event::ChannelDelete(channel) => handler.on_channel_delete(ctx, channel),
+                Event::GuildMemberAdd(member) => handler.on_member_join(ctx, member),
+                Event::GuildMemberRemove(removed) => handler.on_member_leave(ctx, removed),
+                Event::MessageCreate(msg) => handler.on_message(ctx, msg),
+                Event::MessageUpdate(update) => handler.on_message_update(ctx, update),
+                Event::MessageDelete(deleted) => handler.on_message_delete(ctx, deleted),
+                Event::MessageReactionAdd(reaction) => handler.on_reaction_add(ctx, reaction),
+                Event::MessageReactionRemove(reaction) => handler.on_reaction_remove(ctx, reaction),
+                Event::Unknown { name, raw } => handler.on_unknown(ctx, name, raw),
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+// Keeps track of the heartbeating of a single gateway connection. The client
+// must send a heartbeat every <heartbeat_interval> ms and the server answers
+// each one with a heartbeat ACK. If no ACK has arrived when the next heartbeat
+// is due the connection is considered zombied and should be reconnected
+// https://discordapp.com/developers/docs/topics/gateway#heartbeating
+#[derive(Debug)]
+pub struct Heartbeat {
+    interval: Duration,
+    next_beat: Instant,
+    last_sent: Option<Instant>,
+    last_ack: Option<Instant>,
+    awaiting_ack: bool,
+}
+
+impl Heartbeat {
+    // Creates the heartbeat state from the interval received in the hello
+    // message. The first heartbeat is scheduled after interval * jitter where
+    // jitter is a random value between 0 and 1 so that reconnecting clients
+    // don't all heartbeat at the same time
+    pub fn new(interval_ms: u64) -> Heartbeat {
+        let interval = Duration::from_millis(interval_ms);
+        let jitter: f64 = rand::thread_rng().gen();
+        let first_delay = Duration::from_millis((interval_ms as f64 * jitter) as u64);
+        Heartbeat {
+            interval,
+            next_beat: Instant::now() + first_delay,
+            last_sent: None,
+            last_ack: None,
+            awaiting_ack: false,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    // Point in time when the next heartbeat should be sent
+    pub fn next_beat(&self) -> Instant {
+        self.next_beat
+    }
+
+    // A connection is zombied if the last sent heartbeat never was acknowledged
+    pub fn is_zombied(&self) -> bool {
+        self.awaiting_ack
+    }
+
+    // Registers a heartbeat sent on schedule and schedules the next one
+    pub fn beat(&mut self, now: Instant) {
+        self.sent(now);
+        self.next_beat = now + self.interval;
+    }
+
+    // Registers a heartbeat sent outside of the schedule, e.g. when the server
+    // requests one with opcode 1. Does not move the next scheduled heartbeat
+    pub fn sent(&mut self, now: Instant) {
+        self.last_sent = Some(now);
+        self.awaiting_ack = true;
+    }
+
+    // Registers a received heartbeat ACK (opcode 11)
+    pub fn acknowledge(&mut self, now: Instant) {
+        self.last_ack = Some(now);
+        self.awaiting_ack = false;
+    }
+
+    // Time between the last sent heartbeat and its ACK
+    pub fn latency(&self) -> Option<Duration> {
+        match (self.last_sent, self.last_ack) {
+            (Some(sent), Some(ack)) if ack >= sent => Some(ack - sent),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_beat_is_within_interval() {
+        let before = Instant::now();
+        let heartbeat = Heartbeat::new(1000);
+        assert_eq!(heartbeat.interval(), Duration::from_millis(1000));
+        assert!(heartbeat.next_beat() >= before);
+        assert!(heartbeat.next_beat() <= Instant::now() + heartbeat.interval());
+        assert!(!heartbeat.is_zombied());
+        assert_eq!(heartbeat.latency(), None);
+    }
+
+    #[test]
+    fn beat_schedules_next_and_awaits_ack() {
+        let mut heartbeat = Heartbeat::new(1000);
+        let now = Instant::now();
+        heartbeat.beat(now);
+        assert_eq!(heartbeat.next_beat(), now + Duration::from_millis(1000));
+        assert!(heartbeat.is_zombied());
+    }
+
+    #[test]
+    fn ack_clears_zombie_and_measures_latency() {
+        let mut heartbeat = Heartbeat::new(1000);
+        let now = Instant::now();
+        heartbeat.beat(now);
+        heartbeat.acknowledge(now + Duration::from_millis(40));
+        assert!(!heartbeat.is_zombied());
+        assert_eq!(heartbeat.latency(), Some(Duration::from_millis(40)));
+    }
+
+    #[test]
+    fn missing_ack_before_next_beat_is_zombied() {
+        let mut heartbeat = Heartbeat::new(1000);
+        let now = Instant::now();
+        heartbeat.beat(now);
+        heartbeat.acknowledge(now + Duration::from_millis(10));
+        heartbeat.beat(now + Duration::from_millis(1000));
+        assert!(heartbeat.is_zombied());
+        // The ack belongs to an earlier heartbeat
+        assert_eq!(heartbeat.latency(), None);
+    }
+
+    #[test]
+    fn requested_heartbeat_keeps_schedule() {
+        let mut heartbeat = Heartbeat::new(1000);
+        let now = Instant::now();
+        heartbeat.beat(now);
+        heartbeat.acknowledge(now);
+        heartbeat.sent(now + Duration::from_millis(300));
+        assert_eq!(heartbeat.next_beat(), now + Duration::from_millis(1000));
+        assert!(heartbeat.is_zombied());
+        heartbeat.acknowledge(now + Duration::from_millis(350));
+        assert!(!heartbeat.is_zombied());
+        assert_eq!(heartbeat.latency(), Some(Duration::from_millis(50)));
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, Url};
+
+use super::api_error::DiscordApiError;
+use super::ratelimit::{RateLimiter, Route};
+use super::super::error::Error;
+
+// Max number of times a request is retried after being rate limited
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+// REST client for the discord api. Every request waits for the rate limit of
+// its route and is retried when the api responds with 429 Too Many Requests.
+// Clones share the same rate limits
+#[derive(Clone)]
+pub struct Http {
+    client: Client,
+    // Versioned base url that api paths are relative to, ending with a slash
+    api_url: Url,
+    ratelimiter: Arc<RateLimiter>,
+}
+
+impl Http {
+    pub fn new(client: Client, api_url: Url) -> Http {
+        Http {
+            client,
+            api_url,
+            ratelimiter: Arc::new(RateLimiter::new()),
+        }
+    }
+
+    // Full url of an api path such as "guilds/123/channels"
+    pub fn url(&self, path: &str) -> Result<Url, Error> {
+        Ok(self.api_url.join(path)?)
+    }
+
+    // Sends the request created by build, which is called again for every retry.
+    // Fails if the token is rejected, the route stays rate limited or with the
+    // api's error for other non 2xx responses
+    pub fn request<F>(&self, method: Method, url: &Url, build: F) -> Result<Response, Error>
+        where F: Fn(&Client) -> RequestBuilder {
+        let route = Route::new(&method, url);
+        let mut retries = 0;
+        loop {
+            self.ratelimiter.wait(&route);
+            let mut resp = build(&self.client).send()?;
+            self.ratelimiter.update(&route, resp.headers());
+
+            match resp.status() {
+                StatusCode::UNAUTHORIZED => return Err(Error::Unauthorized),
+                StatusCode::TOO_MANY_REQUESTS => {
+                    if retries >= MAX_RATE_LIMIT_RETRIES {
+                        return Err(Error::RateLimited(route.to_string()));
+                    }
+                },
+                status if !status.is_success() => {
+                    let body = resp.text()?;
+                    return Err(Error::Api(DiscordApiError::parse(status, &body)));
+                },
+                _ => return Ok(resp),
+            }
+            self.ratelimiter.rate_limited(&route, resp.headers());
+            retries += 1;
+        }
+    }
+
+    pub fn get(&self, url: &Url) -> Result<Response, Error> {
+        self.request(Method::GET, url, |c| c.get(url.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    const TOO_MANY_REQUESTS: &str = "HTTP/1.1 429 Too Many Requests\r\n\
+        Retry-After: 0.2\r\n\
+        X-RateLimit-Bucket: abcd\r\n\
+        X-RateLimit-Limit: 5\r\n\
+        X-RateLimit-Remaining: 0\r\n\
+        X-RateLimit-Reset-After: 0.2\r\n\
+        Content-Type: application/json\r\n\
+        Content-Length: 2\r\n\
+        Connection: close\r\n\r\n{}";
+    const OK: &str = "HTTP/1.1 200 OK\r\n\
+        Content-Type: application/json\r\n\
+        Content-Length: 2\r\n\
+        Connection: close\r\n\r\n{}";
+
+    // Stands in for the api by answering one request per connection with the
+    // given responses in order. Returns the api url and a handle that yields
+    // the time each request arrived
+    fn serve(responses: Vec<&'static str>) -> (Url, JoinHandle<Vec<Instant>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/api/v6/", listener.local_addr().unwrap())).unwrap();
+        let handle = thread::spawn(move || {
+            let mut arrivals = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                // Requests without a body end with the blank line after the headers
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    assert!(n > 0, "Connection closed before the request was complete");
+                    request.extend_from_slice(&buf[..n]);
+                }
+                arrivals.push(Instant::now());
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            arrivals
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn retries_after_rate_limit() {
+        let (api_url, server) = serve(vec![TOO_MANY_REQUESTS, OK]);
+        let http = Http::new(Client::new(), api_url);
+        let url = http.url("channels/123/messages").unwrap();
+        assert!(http.get(&url).unwrap().status().is_success());
+
+        let arrivals = server.join().unwrap();
+        assert_eq!(arrivals.len(), 2);
+        let wait = arrivals[1] - arrivals[0];
+        assert!(wait >= Duration::from_millis(200) && wait < Duration::from_secs(2), "{:?}", wait);
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let retries = MAX_RATE_LIMIT_RETRIES as usize;
+        let (api_url, server) = serve(vec![TOO_MANY_REQUESTS; retries + 1]);
+        let http = Http::new(Client::new(), api_url);
+        let url = http.url("channels/123/messages").unwrap();
+        match http.get(&url) {
+            Err(Error::RateLimited(route)) => assert_eq!(route, "GET/api/v6/channels/123/messages"),
+            other => panic!("Expected a rate limit error, got {:?}", other),
+        }
+
+        let arrivals = server.join().unwrap();
+        assert_eq!(arrivals.len(), retries + 1);
+        for pair in arrivals.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(200), "{:?}", pair[1] - pair[0]);
+        }
+    }
+}
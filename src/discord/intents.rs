@@ -0,0 +1,87 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+// Gateway intents decide which events the gateway sends to the bot. Privileged
+// intents must additionally be enabled for the bot in the developer portal.
+// Intents are (de)serialized as the raw bitfield
+// https://discordapp.com/developers/docs/topics/gateway#gateway-intents
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Intents(u64);
+
+// Intent names as written in the config file together with their bit and
+// whether the intent is privileged
+const INTENTS: [(&str, u64, bool); 16] = [
+    ("GUILDS", 1 << 0, false),
+    ("GUILD_MEMBERS", 1 << 1, true),
+    ("GUILD_BANS", 1 << 2, false),
+    ("GUILD_EMOJIS", 1 << 3, false),
+    ("GUILD_INTEGRATIONS", 1 << 4, false),
+    ("GUILD_WEBHOOKS", 1 << 5, false),
+    ("GUILD_INVITES", 1 << 6, false),
+    ("GUILD_VOICE_STATES", 1 << 7, false),
+    ("GUILD_PRESENCES", 1 << 8, true),
+    ("GUILD_MESSAGES", 1 << 9, false),
+    ("GUILD_MESSAGE_REACTIONS", 1 << 10, false),
+    ("GUILD_MESSAGE_TYPING", 1 << 11, false),
+    ("DIRECT_MESSAGES", 1 << 12, false),
+    ("DIRECT_MESSAGE_REACTIONS", 1 << 13, false),
+    ("DIRECT_MESSAGE_TYPING", 1 << 14, false),
+    ("MESSAGE_CONTENT", 1 << 15, true),
+];
+
+impl Intents {
+    // Names of all privileged intents that are part of these intents
+    pub fn privileged(self) -> Vec<&'static str> {
+        INTENTS.iter()
+            .filter(|(_, bit, privileged)| *privileged && self.0 & bit != 0)
+            .map(|(name, _, _)| *name)
+            .collect()
+    }
+}
+
+// Intents used when none are configured. Only contains non privileged intents
+// so the bot can connect without any changes in the developer portal
+impl Default for Intents {
+    fn default() -> Intents {
+        Intents::from_str("GUILDS,GUILD_MESSAGES,GUILD_MESSAGE_REACTIONS,DIRECT_MESSAGES")
+            .expect("Default intents are valid")
+    }
+}
+
+// Parses intents either from the raw bitfield number or from a list of intent
+// names separated by ',' or '|', e.g. "GUILDS,GUILD_MESSAGES"
+impl FromStr for Intents {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Intents, String> {
+        let s = s.trim();
+        if let Ok(bits) = s.parse::<u64>() {
+            return Ok(Intents(bits));
+        }
+
+        let mut bits = 0;
+        for name in s.split(&[',', '|'][..]) {
+            let name = name.trim().to_uppercase();
+            if name.is_empty() {
+                continue;
+            }
+            match INTENTS.iter().find(|(n, _, _)| *n == name) {
+                Some((_, bit, _)) => bits |= bit,
+                None => return Err(format!("Unknown gateway intent: {}", name)),
+            }
+        }
+        Ok(Intents(bits))
+    }
+}
+
+impl fmt::Display for Intents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = INTENTS.iter()
+            .filter(|(_, bit, _)| self.0 & bit != 0)
+            .map(|(name, _, _)| *name)
+            .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
@@ -1,12 +1,38 @@
+mod api_error;
 mod gateway;
-use super::config::Settings;
-use reqwest::{Client, Url};
-use serde::{Deserialize};
+mod heartbeat;
+mod cache;
+mod close_code;
+mod etf;
+mod events;
+mod handler;
+mod http;
+mod intents;
+mod payload;
+mod ratelimit;
+mod session_limit;
+mod shard;
+mod snowflake;
+mod zlib_stream;
+pub use self::api_error::DiscordApiError;
+pub use self::close_code::GatewayCloseCode;
+use self::api_error::UNKNOWN_CHANNEL;
+pub use self::events::Event;
+pub use self::gateway::{gateway_info, PresenceSender};
+pub use self::handler::{Context, EventHandler};
+pub use self::http::Http;
+use self::handler::Dispatcher;
+pub use self::intents::Intents;
+pub use self::shard::Shards;
+use super::config::{Delivery, Settings, SharedSettings};
+use super::error::Error;
+use reqwest::{Client, Method, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::sync::{mpsc, Arc};
 use std::{thread, time};
 
-const API_BASE_URL: &str = "https://discordapp.com/api/";
-
 #[derive(PartialEq)]
 enum ChannelType {
     Text = 0,
@@ -21,20 +47,98 @@ enum ChannelType {
 
 // Channel fields directly corresponds to a subset of the Discord api
 // channel response json
-#[derive(Deserialize, Debug)]
-struct Channel{
-    #[serde(alias = "type")]
-    ctype: u8,
-    id: String,
-    name: String,
-    last_message_id: Option<String>,
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Channel{
+    #[serde(rename = "type")]
+    pub ctype: u8,
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub id: String,
+    // Not sent for private channels
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, deserialize_with = "snowflake::option")]
+    pub guild_id: Option<String>,
+    #[serde(default, deserialize_with = "snowflake::option")]
+    pub last_message_id: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Message{
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub id: String,
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub channel_id: String,
+    // Not sent for messages fetched through the REST api
+    #[serde(default, deserialize_with = "snowflake::option")]
+    pub guild_id: Option<String>,
+    pub author: User,
+    pub content: String,
+    pub timestamp: String,
+    pub edited_timestamp: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct User{
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub id: String,
+    pub username: String,
+    pub discriminator: String,
+    #[serde(default)]
+    pub bot: bool,
+}
+
+// Guild that is not yet available to the bot, sent in the ready event before
+// the full guild is received through a guild create event
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UnavailableGuild{
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub id: String,
+    #[serde(default)]
+    pub unavailable: bool,
+}
+
+// Guild fields directly corresponds to a subset of the guild create event json
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Guild{
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub id: String,
+    pub name: String,
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub owner_id: String,
+    pub member_count: Option<u64>,
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+    #[serde(default)]
+    pub members: Vec<Member>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Member{
+    pub user: User,
+    pub nick: Option<String>,
+    #[serde(default, deserialize_with = "snowflake::vec")]
+    pub roles: Vec<String>,
+    pub joined_at: String,
+    // Only sent in guild member events
+    #[serde(default, deserialize_with = "snowflake::option")]
+    pub guild_id: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Emoji{
+    // None for unicode emojis
+    #[serde(default, deserialize_with = "snowflake::option")]
+    pub id: Option<String>,
+    // None for deleted custom emojis
+    pub name: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
-struct Message{
-    id: String,
-    channel_id: String,
-    content: String,
+// The application that owns the bot, its id is the client id
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Application{
+    #[serde(deserialize_with = "snowflake::deserialize")]
+    pub id: String,
+    pub name: String,
 }
 
 impl ChannelType {
@@ -73,20 +177,18 @@ impl Channel {
         self.last_message_id = Some(msg.id.clone());
     }
 
-    fn get_new_messages(&mut self, client: &Client) -> Vec<Message> {
-        let url = Url::parse(&format!("{}channels/{}/messages", 
-                                     API_BASE_URL, 
-                                     self.id)).unwrap();
-        let mut req = client.get(url.clone());
-
-        req = req.query(&[("after", self.last_message_id.as_ref().unwrap())]);
-                                
-        let mut resp = req.send().unwrap();
-        let body = resp.text().unwrap();
+    fn get_new_messages(&mut self, http: &Http) -> Result<Vec<Message>, Error> {
+        let url = http.url(&format!("channels/{}/messages", self.id))?;
+        // Channels without messages have every message after id 0 as new
+        let after = self.last_message_id.as_deref().unwrap_or("0");
+        let mut resp = http.request(Method::GET, &url, |client| {
+            client.get(url.clone()).query(&[("after", after)])
+        })?;
+        let body = resp.text()?;
         let v : Vec<Message> = match serde_json::from_str(&body) {
             Err(e) => {
-                println!("Something went wrong with deserializing json from url: {}", url);
-                panic!(e);
+                warn!("Something went wrong with deserializing json from url: {}", url);
+                return Err(Error::from(e));
             },
             Ok(a) => a,
         };
@@ -97,7 +199,7 @@ impl Channel {
             self.update_last_message(msg);
         }
 
-        v
+        Ok(v)
     }
 }
 impl fmt::Display for Channel {
@@ -106,42 +208,73 @@ impl fmt::Display for Channel {
     }
 }
 
-pub fn test_connection(settings: &Settings) -> Result<(), reqwest::Error>{
-    println!("Client: {:?}", settings.client);
-    reqwest::get("https://httpbin.org/get")?;
-    Ok(())
+fn invalid_setting(setting: &'static str, reason: String) -> Error {
+    Error::InvalidSetting { setting, reason }
 }
 
+// Checks the token, client id and guild id against the discord api. The
+// returned error tells which of the values is wrong
+pub fn validate_settings(settings: &Settings) -> Result<(), Error> {
+    let http = match build_http(settings) {
+        Ok(http) => http,
+        Err(Error::InvalidHeader(_)) => {
+            return Err(invalid_setting("token", String::from("contains characters that aren't allowed in a token")));
+        },
+        Err(e) => return Err(e),
+    };
+
+    let user: User = match get_json(&http, "users/@me") {
+        Err(Error::Unauthorized) => {
+            return Err(invalid_setting("token", String::from("rejected by discord")));
+        },
+        res => res?,
+    };
+    info!("Authenticated as {}#{}", user.username, user.discriminator);
+
+    let application: Application = get_json(&http, "oauth2/applications/@me")?;
+    if application.id != settings.client {
+        return Err(invalid_setting("client", format!(
+            "{} is not the id of the token's application {} ({})",
+            settings.client, application.name, application.id)));
+    }
 
-// TODO: Tests config values' validity for the discord API
-fn settings_valid(_settings: &Settings) -> bool {
-    true
+    let guild: Guild = match get_json(&http, &format!("guilds/{}", settings.guild)) {
+        Err(Error::Api(ref e)) if e.is_missing_access() => {
+            return Err(invalid_setting("guild", format!(
+                "the bot is not a member of guild {}", settings.guild)));
+        },
+        // Ids that aren't snowflakes are bad requests
+        Err(Error::Api(ref e)) if e.status == 400 || e.status == 404 => {
+            return Err(invalid_setting("guild", format!(
+                "{} is not the id of a known guild", settings.guild)));
+        },
+        res => res?,
+    };
+    info!("Using guild {}", guild.name);
+    Ok(())
 }
 
 // Creates a headervalue struct from a string value
-fn get_as_header(val: &str) -> reqwest::header::HeaderValue {
-    //TODO fix error handling
-    let auth_val = reqwest::header::HeaderValue::from_str(val)
-        .expect(format!("Could not create header value from: {}", val).as_ref());
-    auth_val
+fn get_as_header(val: &str) -> Result<reqwest::header::HeaderValue, Error> {
+    let auth_val = reqwest::header::HeaderValue::from_str(val)?;
+    Ok(auth_val)
 }
 
-fn gen_default_headers(settings: &Settings) -> reqwest::header::HeaderMap {
+fn gen_default_headers(settings: &Settings) -> Result<reqwest::header::HeaderMap, Error> {
     use reqwest::header;
     let mut headers = header::HeaderMap::new();
 
-    // TODO refactor to functions
-    let mut auth = String::from("Bot ");
-    auth.push_str(settings.token.as_ref());
-    let auth_val = get_as_header(&auth);
+    let mut auth_val = get_as_header(&format!("Bot {}", settings.token.expose()))?;
+    // Keeps the token out of the client's debug output
+    auth_val.set_sensitive(true);
 
     headers.insert(header::AUTHORIZATION, auth_val);
-    headers
+    Ok(headers)
 }
 
 // Builds and sets the default values for a http client
-fn build_client(settings: &Settings) -> Result<Client, reqwest::Error> {
-    let headers = gen_default_headers(settings);
+fn build_client(settings: &Settings) -> Result<Client, Error> {
+    let headers = gen_default_headers(settings)?;
     let client = Client::builder()
         .timeout(std::time::Duration::from_secs(10))
         .default_headers(headers)
@@ -150,60 +283,142 @@ fn build_client(settings: &Settings) -> Result<Client, reqwest::Error> {
     Ok(client)
 }
 
+// Builds the rate limited api client for the configured api url and version
+pub fn build_http(settings: &Settings) -> Result<Http, Error> {
+    let api_url = format!("{}/v{}/", settings.api_url.trim_end_matches('/'), settings.api_version);
+    let api_url = Url::parse(&api_url)?;
+    info!("Using discord api at {}", api_url);
+    Ok(Http::new(build_client(settings)?, api_url))
+}
+
 
-fn get_channels(client: &Client, guild: &str) -> Vec<Channel> {
-    let url = Url::parse(&format!("{}guilds/{}/channels", 
-                                 API_BASE_URL, 
-                                 guild)).unwrap();
+// Sends a GET request to the api path and deserializes the json response
+fn get_json<T: DeserializeOwned>(http: &Http, path: &str) -> Result<T, Error> {
+    let url = http.url(path)?;
 
-    let mut resp = client.get(url.clone()).send().unwrap();
-    let body = resp.text().unwrap();
-    let v : Vec<Channel> = match serde_json::from_str(&body) {
+    let mut resp = http.get(&url)?;
+    let body = resp.text()?;
+    match serde_json::from_str(&body) {
         Err(e) => {
-            println!("Something went wrong with deserializing json from url: {}", url);
-            panic!(e);
+            warn!("Something went wrong with deserializing json from url: {}", url);
+            Err(Error::from(e))
         },
-        Ok(a) => a,
-    };
-    v
+        Ok(a) => Ok(a),
+    }
+}
+
+pub fn get_channels(http: &Http, guild: &str) -> Result<Vec<Channel>, Error> {
+    get_json(http, &format!("guilds/{}/channels", guild))
 }
 
-fn get_text_channels(client: &Client, guild: &str) -> Vec<Channel> {
-    let mut v = get_channels(client, guild);
+fn get_text_channels(http: &Http, guild: &str) -> Result<Vec<Channel>, Error> {
+    let mut v = get_channels(http, guild)?;
     v.retain(|c| c.get_channel_type() == ChannelType::Text);
-    v
+    Ok(v)
 }
 
+// Sends a text message to the channel and returns the created message
+pub fn send_message(http: &Http, channel: &str, content: &str) -> Result<Message, Error> {
+    let url = http.url(&format!("channels/{}/messages", channel))?;
+    let body = serde_json::json!({ "content": content });
+    let mut resp = http.request(Method::POST, &url, |client| {
+        client.post(url.clone()).json(&body)
+    })?;
+    Ok(resp.json()?)
+}
 
 
 
-// Starts the bot using Bot Token Authorization Header
-// https://discordapp.com/developers/docs/reference#authentication
-pub fn start_bot(settings: &Settings) {
-    assert!(settings_valid(settings));
-    println!("{}", &settings.client);
-    let client = match build_client(&settings) {
-        Ok(c) => c,
-        Err(e) => panic!(e),
-    };
-    let b = gateway::initiate_gateway(&client);
-    if b {
-        return;
-    }
 
-    let mut v = get_text_channels(&client, &settings.guild);
+// Fallback message delivery that polls every text channel of the guild for
+// new messages over the REST api and sends them as message create events.
+// Channels created after startup are not polled. Channels that fail to be
+// polled are skipped until the next round, and channels the bot can't access
+// or that have been deleted are no longer polled
+fn poll_channel_messages(http: &Http, guild: &str, events: mpsc::Sender<(u32, Event)>) -> Result<(), Error> {
+    let mut v = get_text_channels(http, guild)?;
 
     loop {
 
+        let mut inaccessible = Vec::new();
         for x in &mut v {
-            let msgs = x.get_new_messages(&client);
-            for msg in msgs.iter().rev() {
-                println!("New message: {:?}", msg);
+            let msgs = match x.get_new_messages(http) {
+                Ok(msgs) => msgs,
+                Err(Error::Unauthorized) => return Err(Error::Unauthorized),
+                Err(Error::Api(e)) if e.is_missing_access() || e.code == UNKNOWN_CHANNEL => {
+                    warn!("Stopped polling {}: {}", x, e);
+                    inaccessible.push(x.id.clone());
+                    continue;
+                },
+                Err(e) => {
+                    warn!("Could not poll messages in {}: {}", x, e);
+                    continue;
+                },
+            };
+            for mut msg in msgs.into_iter().rev() {
+                // Messages from the REST api don't contain the guild id
+                msg.guild_id = Some(String::from(guild));
+                if events.send((0, Event::MessageCreate(msg))).is_err() {
+                    return Ok(());
+                }
             }
         }
+        v.retain(|c| !inaccessible.contains(&c.id));
 
-        let ten_millis = time::Duration::from_millis(3000);
-        println!("Sleeping 3000 ms");
-        thread::sleep(ten_millis);
+        let sleep_ms = time::Duration::from_millis(3000);
+        thread::sleep(sleep_ms);
+    }
+}
+
+// Starts the bot using Bot Token Authorization Header
+// https://discordapp.com/developers/docs/reference#authentication
+// Events are received from the gateway, or by polling the REST api if
+// configured, in a separate thread and dispatched to the handlers on the
+// calling thread. The gateway is connected with every shard and presence
+// updates are sent to all of them. Returns when the event source stops, with
+// the error that stopped it
+pub fn start_bot(
+        shared: &SharedSettings,
+        handlers: Vec<Box<dyn EventHandler>>,
+        presence: &PresenceSender,
+        ) -> Result<(), Error> {
+    let settings = shared.read().expect("Settings lock poisoned").clone();
+    debug!("Client: {}", &settings.client);
+    let http = build_http(&settings)?;
+
+    // The event source and the handlers share the rate limits of the client
+    let shards = Shards::new();
+    let mut dispatcher = Dispatcher::new(http.clone(), shards.clone());
+    for handler in handlers {
+        dispatcher.register(handler);
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let source_http = http.clone();
+    let source_settings = Arc::clone(shared);
+    let source_presence = presence.clone();
+    let source = match settings.delivery {
+        Delivery::Gateway => {
+            thread::spawn(move || {
+                shard::run_shards(&source_http, &source_settings, sender, &source_presence, &shards)
+            })
+        },
+        Delivery::Polling => {
+            info!("Polling channels for new messages every 3000 ms");
+            let guild = settings.guild.clone();
+            thread::spawn(move || {
+                poll_channel_messages(&source_http, &guild, sender)
+            })
+        },
+    };
+
+    // The channel is closed when the event source thread stops
+    for (shard, event) in receiver {
+        dispatcher.dispatch(shard, &event);
+    }
+    info!("Event source stopped, closing bot");
+    match source.join() {
+        Ok(res) => res,
+        Err(panic) => std::panic::resume_unwind(panic),
     }
 }
@@ -0,0 +1,459 @@
+use super::{Event, Intents};
+use super::super::config::{Secret, Settings};
+use serde::{de, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
+
+// Gateway opcodes
+// https://discordapp.com/developers/docs/topics/opcodes-and-status-codes#gateway-opcodes
+pub const OP_DISPATCH: i32 = 0;
+pub const OP_HEARTBEAT: i32 = 1;
+pub const OP_IDENTIFY: i32 = 2;
+pub const OP_PRESENCE_UPDATE: i32 = 3;
+pub const OP_VOICE_STATE_UPDATE: i32 = 4;
+pub const OP_RESUME: i32 = 6;
+pub const OP_RECONNECT: i32 = 7;
+pub const OP_REQUEST_GUILD_MEMBERS: i32 = 8;
+pub const OP_INVALID_SESSION: i32 = 9;
+pub const OP_HELLO: i32 = 10;
+pub const OP_HEARTBEAT_ACK: i32 = 11;
+
+#[derive(Debug)]
+pub struct GatewayPayload{
+    pub op: i32, // Op-code
+    pub d: GatewayPayloadData,
+    pub s: Option<i32>, // Sequence number
+    pub t: Option<String>,  // Event name
+}
+
+#[derive(Serialize,Debug)]
+#[serde(untagged)]
+pub enum GatewayPayloadData {
+    // Received
+    Dispatch(Event),
+    // Server asks the client to reconnect and resume
+    Reconnect,
+    // Data is whether the session may be resumed
+    InvalidSession(bool),
+    Hello(HelloMsg),
+    HeartbeatAck,
+
+    // Sent
+    // Heartbeat data is the last received sequence number. Also sent by the
+    // server when it requests a heartbeat
+    Heartbeat(Option<i32>),
+    Identify(IdentifyMsg),
+    PresenceUpdate(PresenceMsg),
+    VoiceStateUpdate(VoiceStateUpdateMsg),
+    Resume(ResumeMsg),
+    RequestGuildMembers(RequestGuildMembersMsg),
+
+    // Opcodes that are not known are kept as raw json
+    Unknown(serde_json::Value),
+}
+
+// After gateway websocket connection is initiated a hello message is sent from server
+#[derive(Deserialize,Serialize,Debug)]
+pub struct HelloMsg{
+    // Client should send a heartbeat to server every <heartbeat_interval> milliseconds
+    pub heartbeat_interval: u64,
+}
+
+// Sent by the client after hello to start a new session
+// https://discordapp.com/developers/docs/topics/gateway#identify
+#[derive(Deserialize,Serialize,Debug,Clone)]
+pub struct IdentifyMsg{
+    pub token: Secret,
+    pub properties: ConnectionProperties,
+    // Number of members where the gateway stops sending offline members of a guild
+    pub large_threshold: u32,
+    pub presence: PresenceMsg,
+    pub intents: Intents,
+    // Shard id and number of shards
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<[u32; 2]>,
+}
+
+#[derive(Deserialize,Serialize,Debug,Clone)]
+pub struct ConnectionProperties{
+    #[serde(rename = "$os")]
+    pub os: String,
+    #[serde(rename = "$browser")]
+    pub browser: String,
+    #[serde(rename = "$device")]
+    pub device: String,
+}
+
+// Presence of the bot, sent in identify or as an update with opcode 3
+#[derive(Deserialize,Serialize,Debug,Clone)]
+pub struct PresenceMsg{
+    pub since: Option<u64>,
+    pub game: Option<serde_json::Value>,
+    pub status: String,
+    pub afk: bool,
+}
+
+// Sent by the client to join, move between or leave voice channels
+#[derive(Deserialize,Serialize,Debug)]
+pub struct VoiceStateUpdateMsg{
+    pub guild_id: String,
+    // None disconnects from voice
+    pub channel_id: Option<String>,
+    pub self_mute: bool,
+    pub self_deaf: bool,
+}
+
+// Sent by the client to resume a previous session after a disconnect
+// https://discordapp.com/developers/docs/topics/gateway#resume
+#[derive(Deserialize,Serialize,Debug,Clone)]
+pub struct ResumeMsg{
+    pub token: Secret,
+    pub session_id: String,
+    // Last received sequence number
+    pub seq: Option<i32>,
+}
+
+// Sent by the client to request members of a guild, answered with guild members chunk events
+#[derive(Deserialize,Serialize,Debug)]
+pub struct RequestGuildMembersMsg{
+    pub guild_id: String,
+    // Members with usernames starting with query, empty string for all members
+    pub query: String,
+    // Max amount of members, 0 for all members
+    pub limit: u32,
+}
+
+// GatewayPayload contains both opcode and data where the format of data is dependant
+// of the opcode. This is the custom intermediate deserialization that first extracts
+// the data as raw json value and deserializes after exstracting the opcode
+impl<'de> Deserialize<'de> for GatewayPayload {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>, {
+            #[derive(Deserialize, Debug)]
+            struct Helper {
+                op: i32,
+                #[serde(default)]
+                d: serde_json::Value,
+                #[serde(default)]
+                s: Option<i32>,
+                #[serde(default)]
+                t: Option<String>,
+            }
+
+            let helper = Helper::deserialize(deserializer)?;
+
+            fn deserialize_payload_data<T>(val: serde_json::Value) -> Result<T, serde_json::Error>
+                    where for<'de> T: serde::Deserialize<'de>{
+                serde_json::from_value(val)
+            }
+
+            let data = match helper.op {
+                OP_DISPATCH => {
+                    let name = helper.t.as_deref().unwrap_or_default();
+                    Event::from_dispatch(name, helper.d).map(GatewayPayloadData::Dispatch)
+                }
+                OP_HEARTBEAT => {
+                    deserialize_payload_data::<Option<i32>>(helper.d)
+                        .map(GatewayPayloadData::Heartbeat)
+                }
+                OP_IDENTIFY => {
+                    deserialize_payload_data::<IdentifyMsg>(helper.d)
+                        .map(GatewayPayloadData::Identify)
+                }
+                OP_PRESENCE_UPDATE => {
+                    deserialize_payload_data::<PresenceMsg>(helper.d)
+                        .map(GatewayPayloadData::PresenceUpdate)
+                }
+                OP_VOICE_STATE_UPDATE => {
+                    deserialize_payload_data::<VoiceStateUpdateMsg>(helper.d)
+                        .map(GatewayPayloadData::VoiceStateUpdate)
+                }
+                OP_RESUME => {
+                    deserialize_payload_data::<ResumeMsg>(helper.d)
+                        .map(GatewayPayloadData::Resume)
+                }
+                OP_RECONNECT => Ok(GatewayPayloadData::Reconnect),
+                OP_REQUEST_GUILD_MEMBERS => {
+                    deserialize_payload_data::<RequestGuildMembersMsg>(helper.d)
+                        .map(GatewayPayloadData::RequestGuildMembers)
+                }
+                OP_INVALID_SESSION => {
+                    deserialize_payload_data::<bool>(helper.d)
+                        .map(GatewayPayloadData::InvalidSession)
+                }
+                OP_HELLO => {
+                    deserialize_payload_data::<HelloMsg>(helper.d)
+                        .map(GatewayPayloadData::Hello)
+                }
+                OP_HEARTBEAT_ACK => Ok(GatewayPayloadData::HeartbeatAck),
+                _ => Ok(GatewayPayloadData::Unknown(helper.d)),
+            };
+
+            match data {
+                Ok(data) => {
+                    Ok(GatewayPayload{
+                        op: helper.op,
+                        d: data,
+                        s: helper.s,
+                        t: helper.t,
+                    })
+                },
+                Err(e) => Err(de::Error::custom(format!(
+                    "Could not deserialize gateway payload with opcode {}: {}", helper.op, e))),
+            }
+    }
+}
+
+// Serializes into the same {op, d, s, t} frame as is received from the gateway.
+// The opcode and event name are taken from the data so a payload can't be sent
+// with mismatching opcode and data
+impl Serialize for GatewayPayload {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer, {
+            let mut state = serializer.serialize_struct("GatewayPayload", 4)?;
+            state.serialize_field("op", &self.d.opcode().unwrap_or(self.op))?;
+            state.serialize_field("d", &self.d)?;
+            state.serialize_field("s", &self.s)?;
+            let name = match &self.d {
+                GatewayPayloadData::Dispatch(event) => Some(event.name()),
+                _ => self.t.as_deref(),
+            };
+            state.serialize_field("t", &name)?;
+            state.end()
+    }
+}
+
+impl GatewayPayloadData {
+    // Opcode that the data is sent with, None for unknown opcodes
+    pub fn opcode(&self) -> Option<i32> {
+        let op = match self {
+            GatewayPayloadData::Dispatch(_) => OP_DISPATCH,
+            GatewayPayloadData::Heartbeat(_) => OP_HEARTBEAT,
+            GatewayPayloadData::Identify(_) => OP_IDENTIFY,
+            GatewayPayloadData::PresenceUpdate(_) => OP_PRESENCE_UPDATE,
+            GatewayPayloadData::VoiceStateUpdate(_) => OP_VOICE_STATE_UPDATE,
+            GatewayPayloadData::Resume(_) => OP_RESUME,
+            GatewayPayloadData::Reconnect => OP_RECONNECT,
+            GatewayPayloadData::RequestGuildMembers(_) => OP_REQUEST_GUILD_MEMBERS,
+            GatewayPayloadData::InvalidSession(_) => OP_INVALID_SESSION,
+            GatewayPayloadData::Hello(_) => OP_HELLO,
+            GatewayPayloadData::HeartbeatAck => OP_HEARTBEAT_ACK,
+            GatewayPayloadData::Unknown(_) => return None,
+        };
+        Some(op)
+    }
+}
+
+impl GatewayPayload {
+    // Creates a client sent payload where the opcode is decided by the data
+    pub fn new(d: GatewayPayloadData) -> GatewayPayload {
+        GatewayPayload {
+            op: d.opcode().expect("Client payloads always have a known opcode"),
+            d,
+            s: None,
+            t: None,
+        }
+    }
+
+    // Heartbeat (opcode 1) sent by the client containing the last received sequence number
+    pub fn heartbeat(sequence: Option<i32>) -> GatewayPayload {
+        GatewayPayload::new(GatewayPayloadData::Heartbeat(sequence))
+    }
+
+    // Identify (opcode 2) sent by the client to start a new session
+    pub fn identify(identify: IdentifyMsg) -> GatewayPayload {
+        GatewayPayload::new(GatewayPayloadData::Identify(identify))
+    }
+
+    // Resume (opcode 6) sent by the client to continue a previous session
+    pub fn resume(resume: ResumeMsg) -> GatewayPayload {
+        GatewayPayload::new(GatewayPayloadData::Resume(resume))
+    }
+}
+
+impl PresenceMsg {
+    // Presence configured in the settings, the activity is shown as a game being played
+    pub fn new(settings: &Settings) -> PresenceMsg {
+        PresenceMsg {
+            since: None,
+            game: settings.activity.as_ref()
+                .map(|name| serde_json::json!({ "name": name, "type": 0 })),
+            status: settings.status.clone(),
+            afk: false,
+        }
+    }
+}
+
+impl IdentifyMsg {
+    pub fn new(settings: &Settings) -> IdentifyMsg {
+        let privileged = settings.intents.privileged();
+        if !privileged.is_empty() {
+            warn!("Privileged intents requested, these must be enabled in the developer portal: {}",
+                     privileged.join(","));
+        }
+        IdentifyMsg {
+            token: settings.token.clone(),
+            properties: ConnectionProperties {
+                os: String::from(std::env::consts::OS),
+                browser: String::from("ruuster-discord"),
+                device: String::from("ruuster-discord"),
+            },
+            large_threshold: 50,
+            presence: PresenceMsg::new(settings),
+            intents: settings.intents,
+            shard: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::etf;
+    use serde_json::Value;
+
+    // Frames as sent by the gateway, each must serialize back into the same json
+    const FIXTURES: [&str; 8] = [
+        r#"{"op":1,"d":251,"s":null,"t":null}"#,
+        r#"{"op":1,"d":null,"s":null,"t":null}"#,
+        r#"{"op":7,"d":null,"s":null,"t":null}"#,
+        r#"{"op":9,"d":false,"s":null,"t":null}"#,
+        r#"{"op":10,"d":{"heartbeat_interval":41250},"s":null,"t":null}"#,
+        r#"{"op":11,"d":null,"s":null,"t":null}"#,
+        r#"{"op":0,"d":{"id":"223456789012345678","channel_id":"323456789012345678",
+            "guild_id":"42","author":{"id":"423456789012345678","username":"u",
+            "discriminator":"0002","bot":false},"content":"!ping",
+            "timestamp":"2020-01-01T00:00:00+00:00","edited_timestamp":null},
+            "s":3,"t":"MESSAGE_CREATE"}"#,
+        r#"{"op":0,"d":{"new_field":[1,2]},"s":4,"t":"SOME_NEW_EVENT"}"#,
+    ];
+
+    fn parse(json: &str) -> GatewayPayload {
+        serde_json::from_str(json).expect("Fixture is a valid payload")
+    }
+
+    #[test]
+    fn fixtures_round_trip() {
+        for fixture in FIXTURES.iter() {
+            let expected: Value = serde_json::from_str(fixture).unwrap();
+            let payload = parse(fixture);
+            assert_eq!(serde_json::to_value(&payload).unwrap(), expected, "{}", fixture);
+        }
+    }
+
+    #[test]
+    fn opcodes_map_to_data() {
+        assert!(matches!(parse(FIXTURES[0]).d, GatewayPayloadData::Heartbeat(Some(251))));
+        assert!(matches!(parse(FIXTURES[2]).d, GatewayPayloadData::Reconnect));
+        assert!(matches!(parse(FIXTURES[3]).d, GatewayPayloadData::InvalidSession(false)));
+        match parse(FIXTURES[4]).d {
+            GatewayPayloadData::Hello(hello) => assert_eq!(hello.heartbeat_interval, 41250),
+            d => panic!("Expected hello, got {:?}", d),
+        }
+        assert!(matches!(parse(FIXTURES[5]).d, GatewayPayloadData::HeartbeatAck));
+    }
+
+    #[test]
+    fn dispatch_keeps_sequence_and_event() {
+        let payload = parse(FIXTURES[6]);
+        assert_eq!(payload.s, Some(3));
+        match payload.d {
+            GatewayPayloadData::Dispatch(Event::MessageCreate(msg)) => assert_eq!(msg.content, "!ping"),
+            d => panic!("Expected message create, got {:?}", d),
+        }
+        match parse(FIXTURES[7]).d {
+            GatewayPayloadData::Dispatch(Event::Unknown { name, .. }) => assert_eq!(name, "SOME_NEW_EVENT"),
+            d => panic!("Expected unknown event, got {:?}", d),
+        }
+    }
+
+    #[test]
+    fn mismatched_known_event_falls_back_to_unknown() {
+        let payload = parse(r#"{"op":0,"d":{"id":5},"s":7,"t":"MESSAGE_CREATE"}"#);
+        assert_eq!(payload.s, Some(7));
+        match payload.d {
+            GatewayPayloadData::Dispatch(Event::Unknown { name, raw }) => {
+                assert_eq!(name, "MESSAGE_CREATE");
+                assert_eq!(raw["id"], 5);
+            },
+            d => panic!("Expected unknown event, got {:?}", d),
+        }
+    }
+
+    #[test]
+    fn malformed_ready_is_an_error() {
+        let fixture = r#"{"op":0,"d":{"v":6},"s":1,"t":"READY"}"#;
+        assert!(serde_json::from_str::<GatewayPayload>(fixture).is_err());
+    }
+
+    #[test]
+    fn unknown_opcode_keeps_raw_data() {
+        let fixture = r#"{"op":42,"d":{"x":1},"s":null,"t":null}"#;
+        let payload = parse(fixture);
+        assert_eq!(payload.op, 42);
+        assert!(matches!(&payload.d, GatewayPayloadData::Unknown(d) if d["x"] == 1));
+        assert_eq!(serde_json::to_value(&payload).unwrap(), serde_json::from_str::<Value>(fixture).unwrap());
+    }
+
+    #[test]
+    fn missing_fields_default() {
+        let payload = parse(r#"{"op":11}"#);
+        assert!(matches!(payload.d, GatewayPayloadData::HeartbeatAck));
+        assert_eq!(payload.s, None);
+        assert_eq!(payload.t, None);
+    }
+
+    #[test]
+    fn invalid_data_is_an_error() {
+        let err = serde_json::from_str::<GatewayPayload>(r#"{"op":10,"d":{"heartbeat_interval":"soon"}}"#)
+            .unwrap_err();
+        assert!(err.to_string().contains("opcode 10"), "{}", err);
+    }
+
+    // The gateway sends snowflakes as integers in etf payloads
+    fn snowflakes_as_integers(value: Value) -> Value {
+        match value {
+            Value::String(s) if s.len() >= 15 && s.chars().all(|c| c.is_ascii_digit()) => {
+                Value::from(s.parse::<u64>().unwrap())
+            },
+            Value::Array(items) => Value::Array(items.into_iter().map(snowflakes_as_integers).collect()),
+            Value::Object(map) => Value::Object(map.into_iter()
+                .map(|(k, v)| (k, snowflakes_as_integers(v)))
+                .collect()),
+            value => value,
+        }
+    }
+
+    fn parse_etf(bytes: &[u8]) -> GatewayPayload {
+        serde_json::from_value(etf::decode(bytes).unwrap()).expect("Fixture is a valid payload")
+    }
+
+    #[test]
+    fn etf_fixtures_decode_like_json() {
+        for fixture in FIXTURES.iter() {
+            let json: Value = serde_json::from_str(fixture).unwrap();
+            let bytes = etf::encode(&snowflakes_as_integers(json));
+            let from_etf = serde_json::to_value(parse_etf(&bytes)).unwrap();
+            let from_json = serde_json::to_value(parse(fixture)).unwrap();
+            assert_eq!(from_etf, from_json, "{}", fixture);
+        }
+    }
+
+    #[test]
+    fn client_payloads_round_trip_over_etf() {
+        let presence = GatewayPayload::new(GatewayPayloadData::PresenceUpdate(PresenceMsg {
+            since: Some(1_600_000_000_000),
+            game: Some(serde_json::json!({"name": "chess", "type": 0})),
+            status: String::from("idle"),
+            afk: false,
+        }));
+        let resume = GatewayPayload::resume(ResumeMsg {
+            token: Secret::new(String::from("token")),
+            session_id: String::from("session"),
+            seq: Some(12),
+        });
+        for payload in [presence, resume, GatewayPayload::heartbeat(None)].iter() {
+            let json = serde_json::to_value(payload).unwrap();
+            let from_etf = parse_etf(&etf::encode(&json));
+            assert_eq!(serde_json::to_value(from_etf).unwrap(), json);
+        }
+    }
+}
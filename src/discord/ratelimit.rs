@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use reqwest::header::HeaderMap;
+use reqwest::{Method, Url};
+
+// Path segments whose following id is a major parameter. Requests to the same
+// route with different major parameters have separate rate limits
+const MAJOR_PARAMETERS: [&str; 3] = ["channels", "guilds", "webhooks"];
+
+// Rate limit state of a single bucket as last reported by the api
+#[derive(Debug)]
+struct Bucket {
+    limit: u32,
+    remaining: u32,
+    reset: Instant,
+    // Length of the bucket's rate limit window
+    window: Duration,
+}
+
+// Keeps track of discord's per route rate limit buckets and the global rate
+// limit. Requests wait until their bucket has requests remaining
+// https://discordapp.com/developers/docs/topics/rate-limits
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    // Route to the bucket hash from the X-RateLimit-Bucket header. Routes
+    // sharing a hash share the same limit
+    routes: Mutex<HashMap<String, String>>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    // Time when the global rate limit is lifted
+    global_reset: Mutex<Option<Instant>>,
+}
+
+// A request route, e.g. "GET/channels/123/messages/{id}", where all ids
+// except for major parameters are replaced
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    key: String,
+    major: String,
+}
+
+impl Route {
+    pub fn new(method: &Method, url: &Url) -> Route {
+        let mut key = String::from(method.as_str());
+        let mut major = String::new();
+        let mut previous = "";
+        for segment in url.path_segments().into_iter().flatten() {
+            let is_id = !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit());
+            key.push('/');
+            if is_id && MAJOR_PARAMETERS.contains(&previous) {
+                key.push_str(segment);
+                major = format!("{}/{}", previous, segment);
+            } else if is_id {
+                key.push_str("{id}");
+            } else {
+                key.push_str(segment);
+            }
+            previous = segment;
+        }
+        Route { key, major }
+    }
+}
+
+impl std::fmt::Display for Route {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.key)
+    }
+}
+
+// Parses a header value, None if missing or malformed
+fn header<T: FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+    headers.get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+// Converts a seconds value like "1.250" from a rate limit header
+fn seconds(secs: f64) -> Duration {
+    Duration::from_millis((secs * 1000.0).ceil() as u64)
+}
+
+impl RateLimiter {
+    pub fn new() -> RateLimiter {
+        RateLimiter::default()
+    }
+
+    // Key of the bucket the route belongs to. Routes without a known bucket
+    // hash are limited on their own until the api reports their bucket
+    fn bucket_key(&self, route: &Route) -> String {
+        let routes = self.routes.lock().expect("Rate limiter lock poisoned");
+        match routes.get(&route.key) {
+            Some(hash) => format!("{}:{}", hash, route.major),
+            None => route.key.clone(),
+        }
+    }
+
+    // Reserves a request on the route's bucket. Returns the time to wait if
+    // the global limit is hit or the bucket has no requests remaining
+    fn acquire(&self, route: &Route) -> Option<Duration> {
+        let now = Instant::now();
+        if let Some(reset) = *self.global_reset.lock().expect("Rate limiter lock poisoned") {
+            if reset > now {
+                return Some(reset - now);
+            }
+        }
+
+        let key = self.bucket_key(route);
+        let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+        let bucket = buckets.get_mut(&key)?;
+        // The refilled window is assumed to be as long as the last one so
+        // requests sent before a response updates the bucket are still counted
+        if bucket.reset <= now {
+            bucket.remaining = bucket.limit;
+            bucket.reset = now + bucket.window;
+        }
+        if bucket.remaining == 0 {
+            return Some(bucket.reset - now);
+        }
+        bucket.remaining -= 1;
+        None
+    }
+
+    // Blocks the thread until a request may be sent on the route
+    pub fn wait(&self, route: &Route) {
+        while let Some(wait) = self.acquire(route) {
+            debug!("Rate limit reached for {}, waiting {:?}", route, wait);
+            std::thread::sleep(wait);
+        }
+    }
+
+    // Updates the route's bucket from the rate limit headers of a response
+    pub fn update(&self, route: &Route, headers: &HeaderMap) {
+        if let Some(hash) = header::<String>(headers, "x-ratelimit-bucket") {
+            self.routes.lock().expect("Rate limiter lock poisoned").insert(route.key.clone(), hash);
+        }
+
+        let limit = header::<u32>(headers, "x-ratelimit-limit");
+        let remaining = header::<u32>(headers, "x-ratelimit-remaining");
+        let reset_after = header::<f64>(headers, "x-ratelimit-reset-after");
+        if let (Some(limit), Some(remaining), Some(reset_after)) = (limit, remaining, reset_after) {
+            let key = self.bucket_key(route);
+            let window = seconds(reset_after);
+            self.buckets.lock().expect("Rate limiter lock poisoned").insert(key, Bucket {
+                limit,
+                remaining,
+                reset: Instant::now() + window,
+                window,
+            });
+        }
+    }
+
+    // Handles a 429 response by blocking the route, or every route for global
+    // rate limits, until the retry after time has passed
+    pub fn rate_limited(&self, route: &Route, headers: &HeaderMap) {
+        let retry_after = header::<f64>(headers, "retry-after")
+            .map(seconds)
+            .unwrap_or_else(|| Duration::from_secs(1));
+        let reset = Instant::now() + retry_after;
+
+        if header::<bool>(headers, "x-ratelimit-global").unwrap_or(false) {
+            warn!("Global rate limit reached, retrying in {:?}", retry_after);
+            *self.global_reset.lock().expect("Rate limiter lock poisoned") = Some(reset);
+        } else {
+            warn!("Rate limited on {}, retrying in {:?}", route, retry_after);
+            let key = self.bucket_key(route);
+            let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+            let bucket = buckets.entry(key).or_insert(Bucket {
+                limit: 1,
+                remaining: 0,
+                reset,
+                window: retry_after,
+            });
+            bucket.remaining = 0;
+            bucket.reset = reset;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn route(method: Method, path: &str) -> Route {
+        let url = Url::parse(&format!("https://discordapp.com/api/v6/{}", path)).unwrap();
+        Route::new(&method, &url)
+    }
+
+    fn headers(values: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn bucket_headers(limit: &str, remaining: &str, reset_after: &str) -> HeaderMap {
+        headers(&[
+            ("x-ratelimit-bucket", "abcd"),
+            ("x-ratelimit-limit", limit),
+            ("x-ratelimit-remaining", remaining),
+            ("x-ratelimit-reset-after", reset_after),
+        ])
+    }
+
+    #[test]
+    fn route_keeps_major_parameters() {
+        let messages = route(Method::GET, "channels/123/messages/456");
+        assert_eq!(messages.to_string(), "GET/api/v6/channels/123/messages/{id}");
+        assert_eq!(messages.major, "channels/123");
+        assert_eq!(route(Method::GET, "channels/123/messages/789"), messages);
+        assert_ne!(route(Method::GET, "channels/124/messages/456"), messages);
+        assert_ne!(route(Method::POST, "channels/123/messages/456"), messages);
+
+        let guild = route(Method::GET, "guilds/42/members/7");
+        assert_eq!(guild.to_string(), "GET/api/v6/guilds/42/members/{id}");
+        assert_eq!(guild.major, "guilds/42");
+        assert_eq!(route(Method::GET, "users/@me").major, "");
+    }
+
+    #[test]
+    fn unknown_route_is_not_limited() {
+        let limiter = RateLimiter::new();
+        assert_eq!(limiter.acquire(&route(Method::GET, "users/@me")), None);
+    }
+
+    #[test]
+    fn update_limits_remaining_requests() {
+        let limiter = RateLimiter::new();
+        let messages = route(Method::POST, "channels/123/messages");
+        limiter.update(&messages, &bucket_headers("5", "2", "10"));
+        assert_eq!(limiter.acquire(&messages), None);
+        assert_eq!(limiter.acquire(&messages), None);
+        let wait = limiter.acquire(&messages).expect("Bucket is used up");
+        assert!(wait > Duration::from_secs(9) && wait <= Duration::from_secs(10), "{:?}", wait);
+        // Other major parameters have their own bucket
+        assert_eq!(limiter.acquire(&route(Method::POST, "channels/124/messages")), None);
+    }
+
+    #[test]
+    fn routes_with_same_bucket_hash_share_the_limit() {
+        let limiter = RateLimiter::new();
+        let create = route(Method::POST, "channels/123/messages");
+        let delete = route(Method::DELETE, "channels/123/messages/456");
+        limiter.update(&create, &bucket_headers("5", "1", "10"));
+        limiter.update(&delete, &bucket_headers("5", "1", "10"));
+        assert_eq!(limiter.acquire(&delete), None);
+        assert!(limiter.acquire(&create).is_some());
+    }
+
+    #[test]
+    fn refilled_bucket_counts_requests_until_updated() {
+        let limiter = RateLimiter::new();
+        let messages = route(Method::POST, "channels/123/messages");
+        limiter.update(&messages, &bucket_headers("2", "0", "0.05"));
+        assert!(limiter.acquire(&messages).is_some());
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(limiter.acquire(&messages), None);
+        assert_eq!(limiter.acquire(&messages), None);
+        assert!(limiter.acquire(&messages).is_some());
+    }
+
+    #[test]
+    fn rate_limited_blocks_the_route() {
+        let limiter = RateLimiter::new();
+        let messages = route(Method::POST, "channels/123/messages");
+        limiter.rate_limited(&messages, &headers(&[("retry-after", "2.5")]));
+        let wait = limiter.acquire(&messages).expect("Route is rate limited");
+        assert!(wait > Duration::from_secs(2) && wait <= Duration::from_millis(2500), "{:?}", wait);
+        assert_eq!(limiter.acquire(&route(Method::GET, "users/@me")), None);
+    }
+
+    #[test]
+    fn global_rate_limit_blocks_every_route() {
+        let limiter = RateLimiter::new();
+        let messages = route(Method::POST, "channels/123/messages");
+        limiter.rate_limited(&messages, &headers(&[("retry-after", "1"), ("x-ratelimit-global", "true")]));
+        assert!(limiter.acquire(&messages).is_some());
+        assert!(limiter.acquire(&route(Method::GET, "users/@me")).is_some());
+    }
+}
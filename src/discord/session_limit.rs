@@ -0,0 +1,116 @@
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::gateway::GatewaySessionStartLimit;
+
+// Daily session starts of a bot when the api hasn't said otherwise
+const DEFAULT_TOTAL: u32 = 1000;
+const RESET_INTERVAL_MS: u64 = 24 * 60 * 60 * 1000;
+
+// Session starts left until reset_at, in milliseconds since the unix epoch
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct Budget {
+    total: u32,
+    remaining: u32,
+    reset_at: u64,
+}
+
+impl Budget {
+    // Refills the budget once the reset time has passed
+    fn refresh(&mut self, now: u64) {
+        if now >= self.reset_at {
+            self.remaining = self.total;
+            self.reset_at = now + RESET_INTERVAL_MS;
+        }
+    }
+}
+
+// Every identify starts a new session and discord resets the token of bots
+// that start too many sessions in a day. The remaining starts are persisted
+// in a file so that a bot restarted in a loop can't use up the budget
+pub struct SessionStartLimit {
+    budget: Mutex<Budget>,
+    path: String,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn read_budget(path: &str) -> Option<Budget> {
+    let contents = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(budget) => Some(budget),
+        Err(e) => {
+            warn!("Ignoring invalid session start limit file {}: {}", path, e);
+            None
+        },
+    }
+}
+
+impl SessionStartLimit {
+    // Combines the limit from the api, if it was asked, with the persisted
+    // one. The lowest remaining count is used while the persisted limit
+    // hasn't been reset
+    pub fn load(path: &str, api: Option<&GatewaySessionStartLimit>) -> SessionStartLimit {
+        let now = now_ms();
+        let persisted = read_budget(path).filter(|b| b.reset_at > now);
+        let mut budget = match api {
+            Some(api) => Budget {
+                total: api.total,
+                remaining: api.remaining,
+                reset_at: now + u64::from(api.reset_after),
+            },
+            None => persisted.unwrap_or(Budget {
+                total: DEFAULT_TOTAL,
+                remaining: DEFAULT_TOTAL,
+                reset_at: now + RESET_INTERVAL_MS,
+            }),
+        };
+        if let Some(persisted) = persisted {
+            budget.remaining = budget.remaining.min(persisted.remaining);
+        }
+        info!("{} of {} session starts left, resets in {} s",
+                 budget.remaining, budget.total, budget.reset_at.saturating_sub(now) / 1000);
+
+        let limit = SessionStartLimit {
+            budget: Mutex::new(budget),
+            path: String::from(path),
+        };
+        limit.save(&budget);
+        limit
+    }
+
+    fn save(&self, budget: &Budget) {
+        let json = serde_json::to_string(budget).expect("Session start limit is always serializable");
+        if let Err(e) = fs::write(&self.path, json) {
+            warn!("Could not save session start limit to {}: {}", self.path, e);
+        }
+    }
+
+    // Takes a session start from the budget before identifying. When the
+    // budget is used up every shard is blocked until it resets
+    pub fn acquire(&self, shard: u32) {
+        let mut budget = self.budget.lock().expect("Session start limit lock poisoned");
+        let now = now_ms();
+        budget.refresh(now);
+        if budget.remaining == 0 {
+            let wait = budget.reset_at.saturating_sub(now);
+            error!("All {} session starts are used, shard {} waits {} s for the limit to reset instead of risking a token reset",
+                      budget.total, shard, wait / 1000);
+            thread::sleep(Duration::from_millis(wait));
+            budget.refresh(now_ms());
+        }
+        budget.remaining = budget.remaining.saturating_sub(1);
+        if budget.remaining < budget.total / 10 {
+            warn!("Only {} of {} session starts left", budget.remaining, budget.total);
+        }
+        self.save(&budget);
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::gateway::{self, PresenceReceiver, PresenceSender};
+use super::session_limit::SessionStartLimit;
+use super::{Event, Http};
+use super::super::config::SharedSettings;
+use super::super::error::Error;
+
+// Time between identifies in the same rate limit bucket
+// https://discordapp.com/developers/docs/topics/gateway#sharding-for-very-large-bots
+const IDENTIFY_INTERVAL: Duration = Duration::from_secs(5);
+
+// Connection state of a single shard
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShardStatus {
+    // Waiting for its turn to identify
+    Queued,
+    Connecting,
+    // Identified or resumed and receiving events
+    Ready,
+    // Connection ended and is being reconnected
+    Reconnecting,
+    // Closed with a close code that reconnecting won't fix
+    Stopped,
+}
+
+impl fmt::Display for ShardStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// Status of every shard, shared between the shards and the event handlers.
+// Empty when events are polled instead of received from the gateway
+#[derive(Debug, Clone, Default)]
+pub struct Shards(Arc<Mutex<Vec<ShardStatus>>>);
+
+impl Shards {
+    pub fn new() -> Shards {
+        Shards::default()
+    }
+
+    // Status of each shard indexed by shard id
+    pub fn statuses(&self) -> Vec<ShardStatus> {
+        self.0.lock().expect("Shard status lock poisoned").clone()
+    }
+
+    pub fn count(&self) -> u32 {
+        self.0.lock().expect("Shard status lock poisoned").len() as u32
+    }
+
+    fn init(&self, count: u32) {
+        *self.0.lock().expect("Shard status lock poisoned") = vec![ShardStatus::Queued; count as usize];
+    }
+
+    fn set(&self, shard: u32, status: ShardStatus) {
+        let mut statuses = self.0.lock().expect("Shard status lock poisoned");
+        if let Some(s) = statuses.get_mut(shard as usize) {
+            if *s != status {
+                debug!("Shard {} is {}", shard, status);
+            }
+            *s = status;
+        }
+    }
+}
+
+// A shard as seen by its gateway connections
+#[derive(Clone)]
+pub struct Shard {
+    pub id: u32,
+    pub count: u32,
+    // Dispatched events are sent with the shard id
+    pub events: Sender<(u32, Event)>,
+    pub presence_updates: PresenceReceiver,
+    statuses: Shards,
+}
+
+impl Shard {
+    pub fn set_status(&self, status: ShardStatus) {
+        self.statuses.set(self.id, status);
+    }
+}
+
+// Spaces out identifies so that at most max_concurrency shards identify every
+// 5 seconds, and keeps them within the session start limit. Shard n uses the
+// rate limit bucket n % max_concurrency
+pub struct IdentifyQueue {
+    // When the next identify is allowed in each bucket
+    buckets: Mutex<Vec<Instant>>,
+    limit: SessionStartLimit,
+}
+
+impl IdentifyQueue {
+    fn new(max_concurrency: u32, limit: SessionStartLimit) -> IdentifyQueue {
+        let now = Instant::now();
+        IdentifyQueue {
+            buckets: Mutex::new(vec![now; max_concurrency.max(1) as usize]),
+            limit,
+        }
+    }
+
+    // Blocks until the shard may identify
+    pub fn wait(&self, shard: u32) {
+        self.limit.acquire(shard);
+        let allowed = {
+            let mut buckets = self.buckets.lock().expect("Identify queue lock poisoned");
+            let bucket = shard as usize % buckets.len();
+            let allowed = buckets[bucket].max(Instant::now());
+            buckets[bucket] = allowed + IDENTIFY_INTERVAL;
+            allowed
+        };
+        let now = Instant::now();
+        if allowed > now {
+            debug!("Shard {} waiting {:?} to identify", shard, allowed - now);
+            thread::sleep(allowed - now);
+        }
+    }
+}
+
+// Runs a gateway connection for every shard, each in its own thread. The
+// shard count is taken from the settings or from the api when set to auto.
+// Returns when every shard has stopped, with the error of the first shard
+// that failed
+pub fn run_shards(
+        http: &Http,
+        shared: &SharedSettings,
+        events: Sender<(u32, Event)>,
+        presence: &PresenceSender,
+        statuses: &Shards,
+        ) -> Result<(), Error> {
+    let settings = shared.read().expect("Settings lock poisoned").clone();
+
+    // The api is only asked when it decides something, with a configured
+    // gateway url and shard count the session start limit is only known from
+    // the persisted limit
+    let (url, count, max_concurrency, limit) = match (&settings.gateway_url, settings.shards) {
+        (Some(url), Some(count)) => {
+            (url.clone(), count, 1, SessionStartLimit::load(&settings.session_limit_file, None))
+        },
+        (url, count) => {
+            let info = gateway::gateway_info(http)?;
+            let limit = &info.session_start_limit;
+            (
+                url.clone().unwrap_or(info.url),
+                count.unwrap_or(info.shards),
+                limit.max_concurrency,
+                SessionStartLimit::load(&settings.session_limit_file, Some(limit)),
+            )
+        },
+    };
+    info!("Starting {} shard(s), {} identifying at a time", count, max_concurrency);
+
+    statuses.init(count);
+    let queue = Arc::new(IdentifyQueue::new(max_concurrency, limit));
+    let threads: Vec<_> = (0..count).map(|id| {
+        let shard = Shard {
+            id,
+            count,
+            events: events.clone(),
+            presence_updates: presence.subscribe(),
+            statuses: statuses.clone(),
+        };
+        let shared = Arc::clone(shared);
+        let url = url.clone();
+        let queue = Arc::clone(&queue);
+        thread::spawn(move || {
+            let res = gateway::initiate_gateway(&shared, &shard, &url, &queue);
+            if let Err(e) = &res {
+                error!("Shard {} stopped: {}", shard.id, e);
+            }
+            shard.set_status(ShardStatus::Stopped);
+            res
+        })
+    }).collect();
+    // Events stop when every shard has dropped its sender
+    drop(events);
+
+    let mut result = Ok(());
+    for thread in threads {
+        let res = match thread.join() {
+            Ok(res) => res,
+            Err(panic) => std::panic::resume_unwind(panic),
+        };
+        if result.is_ok() {
+            result = res;
+        }
+    }
+    result
+}
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Deserializer};
+
+// Snowflake ids are strings in json but integers in etf payloads. Id fields
+// deserialize from both into the string form
+// https://discordapp.com/developers/docs/reference#snowflakes
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Id {
+    Text(String),
+    Number(u64),
+}
+
+impl From<Id> for String {
+    fn from(id: Id) -> String {
+        match id {
+            Id::Text(id) => id,
+            Id::Number(id) => id.to_string(),
+        }
+    }
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<String, D::Error>
+        where D: Deserializer<'de> {
+    Id::deserialize(deserializer).map(String::from)
+}
+
+// For optional ids, the field also needs #[serde(default)]
+pub fn option<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+        where D: Deserializer<'de> {
+    Ok(Option::<Id>::deserialize(deserializer)?.map(String::from))
+}
+
+pub fn vec<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+        where D: Deserializer<'de> {
+    Ok(Vec::<Id>::deserialize(deserializer)?.into_iter().map(String::from).collect())
+}
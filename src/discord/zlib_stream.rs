@@ -0,0 +1,125 @@
+use std::io;
+
+use flate2::{Decompress, FlushDecompress, Status};
+
+// Every complete message ends with the suffix of a zlib sync flush
+const ZLIB_SUFFIX: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+// Decompresses gateway messages sent with compress=zlib-stream. The whole
+// connection is a single zlib stream so the inflate context is kept between
+// messages, and a message may be split over several binary frames
+// https://discordapp.com/developers/docs/topics/gateway#transport-compression
+pub struct ZlibStream {
+    decompress: Decompress,
+    // Frames of the message that has not been completed yet
+    buffer: Vec<u8>,
+}
+
+impl ZlibStream {
+    pub fn new() -> ZlibStream {
+        ZlibStream {
+            decompress: Decompress::new(true),
+            buffer: Vec::new(),
+        }
+    }
+
+    // Adds a binary frame and returns the decompressed message once the frame
+    // completes it. Fails if the stream is corrupt, after which the connection
+    // can't be decompressed anymore
+    pub fn push(&mut self, frame: &[u8]) -> Result<Option<Vec<u8>>, io::Error> {
+        self.buffer.extend_from_slice(frame);
+        if !self.buffer.ends_with(&ZLIB_SUFFIX) {
+            return Ok(None);
+        }
+
+        let mut output = Vec::with_capacity(self.buffer.len() * 4);
+        let mut input = &self.buffer[..];
+        loop {
+            let total_in = self.decompress.total_in();
+            let total_out = self.decompress.total_out();
+            let status = self.decompress.decompress_vec(input, &mut output, FlushDecompress::Sync)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let consumed = (self.decompress.total_in() - total_in) as usize;
+            // The gateway never ends the stream, and a call without progress
+            // would otherwise keep growing the output forever
+            if status == Status::StreamEnd {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "zlib stream ended"));
+            }
+            if consumed == 0 && self.decompress.total_out() == total_out {
+                // Without input left, the previous call filled the output
+                // exactly and nothing else was pending
+                if input.is_empty() {
+                    break;
+                }
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "zlib stream made no progress"));
+            }
+            input = &input[consumed..];
+            // Output space left over means everything has been flushed
+            if input.is_empty() && output.len() < output.capacity() {
+                break;
+            }
+            output.reserve(output.capacity());
+        }
+        self.buffer.clear();
+        Ok(Some(output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+
+    // Compresses the message and sync flushes it like the gateway does,
+    // returning the bytes written since the last flush
+    fn compress(encoder: &mut ZlibEncoder<Vec<u8>>, message: &[u8]) -> Vec<u8> {
+        encoder.write_all(message).unwrap();
+        encoder.flush().unwrap();
+        std::mem::take(encoder.get_mut())
+    }
+
+    #[test]
+    fn message_split_over_frames() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let message = br#"{"op":10,"d":{"heartbeat_interval":41250}}"#;
+        let data = compress(&mut encoder, message);
+        assert!(data.ends_with(&ZLIB_SUFFIX));
+
+        let mut stream = ZlibStream::new();
+        let (first, second) = data.split_at(data.len() / 2);
+        assert_eq!(stream.push(first).unwrap(), None);
+        assert_eq!(stream.push(second).unwrap().unwrap(), &message[..]);
+    }
+
+    #[test]
+    fn messages_share_inflate_context() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let first = br#"{"op":11,"d":null}"#;
+        // Repeating the first message lets the encoder refer back to it
+        let second = br#"{"op":11,"d":null}{"op":11,"d":null}"#;
+        let first_data = compress(&mut encoder, first);
+        let second_data = compress(&mut encoder, second);
+
+        let mut stream = ZlibStream::new();
+        assert_eq!(stream.push(&first_data).unwrap().unwrap(), &first[..]);
+        assert_eq!(stream.push(&second_data).unwrap().unwrap(), &second[..]);
+
+        // The second message can't be inflated without the first one
+        assert!(ZlibStream::new().push(&second_data).is_err());
+    }
+
+    #[test]
+    fn ended_stream_is_an_error() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(br#"{"op":11,"d":null}"#).unwrap();
+        let mut data = encoder.finish().unwrap();
+        data.extend_from_slice(&ZLIB_SUFFIX);
+
+        let error = ZlibStream::new().push(&data).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+use std::io;
+
+use reqwest::header::InvalidHeaderValue;
+use reqwest::UrlError;
+
+use super::config::ConfigError;
+use super::discord::{DiscordApiError, GatewayCloseCode};
+
+// Errors that can stop the bot or a single request. Failures that only affect
+// one message or response are logged where they happen and the bot keeps running
+#[derive(Debug)]
+pub enum Error {
+    // Request to the REST api could not be sent or its response read
+    Http(reqwest::Error),
+    // Response or gateway payload could not be (de)serialized
+    Json(serde_json::Error),
+    // Gateway closed the connection with a close code that reconnecting won't fix
+    Gateway(GatewayCloseCode),
+    // Request was still rate limited after retrying, contains the route
+    RateLimited(String),
+    // The api rejected the bot token
+    Unauthorized,
+    // The api responded with an error, e.g. missing access to a channel
+    Api(DiscordApiError),
+    // Missing or invalid configuration
+    Config(ConfigError),
+    // Config file that could not be parsed
+    ConfigFile { path: String, error: ConfigError },
+    // A configured value was rejected by the discord api
+    InvalidSetting { setting: &'static str, reason: String },
+    Io(io::Error),
+    Url(UrlError),
+    InvalidHeader(InvalidHeaderValue),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "HTTP request failed: {}", e),
+            Error::Json(e) => write!(f, "Invalid json: {}", e),
+            Error::Gateway(code) => write!(f, "Gateway closed the connection: {}", code),
+            Error::RateLimited(route) => write!(f, "Rate limited on {}", route),
+            Error::Unauthorized => write!(f, "Unauthorized, check that the bot token is correct"),
+            Error::Api(e) => write!(f, "Discord api error: {}", e),
+            Error::Config(e) => write!(f, "Configuration error: {}", e),
+            Error::ConfigFile { path, error } => write!(f, "Invalid config file {}: {}", path, error),
+            Error::InvalidSetting { setting, reason } => write!(f, "Invalid {}: {}", setting, reason),
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::Url(e) => write!(f, "Invalid url: {}", e),
+            Error::InvalidHeader(e) => write!(f, "Invalid header value: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Api(e) => Some(e),
+            Error::Config(e) => Some(e),
+            Error::ConfigFile { error, .. } => Some(error),
+            Error::Io(e) => Some(e),
+            Error::Url(e) => Some(e),
+            Error::InvalidHeader(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Error {
+        Error::Config(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        Error::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<UrlError> for Error {
+    fn from(e: UrlError) -> Error {
+        Error::Url(e)
+    }
+}
+
+impl From<InvalidHeaderValue> for Error {
+    fn from(e: InvalidHeaderValue) -> Error {
+        Error::InvalidHeader(e)
+    }
+}
@@ -1,18 +1,115 @@
+mod bot;
 mod config;
 mod discord;
+mod error;
 extern crate reqwest;
 extern crate websocket;
 extern crate tokio;
+#[macro_use]
+extern crate log;
+
+use std::env;
+use std::process;
+use std::sync::{Arc, RwLock};
+
+use log::LevelFilter;
+
+use config::{Arguments, Command, Settings};
+use error::Error;
+
+// Logs everything from the bot and only warnings from dependencies, RUST_LOG
+// may change this per module. The overall level is capped by log_level once
+// the settings are loaded
+fn init_logging() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(LevelFilter::Warn)
+        .filter_module("ruuster_discord", LevelFilter::Trace);
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    builder.init();
+    log::set_max_level(LevelFilter::Info);
+}
+
+// Runs the command with the loaded settings. Output meant for the user is
+// printed to stdout, everything else is logged
+fn run_command(args: &Arguments, settings: &Settings) -> Result<(), Error> {
+    match &args.command {
+        Command::Run => {
+            discord::validate_settings(settings)?;
+            info!("Settings OK");
+            // The reloadable settings are changed by the config watcher
+            let shared = Arc::new(RwLock::new(settings.clone()));
+            let presence = discord::PresenceSender::new();
+            let watcher_presence = presence.clone();
+            config::watch(args, &shared, move |s| watcher_presence.update(s))?;
+            discord::start_bot(&shared, bot::handlers(&shared), &presence)
+        },
+        Command::CheckConfig => {
+            discord::validate_settings(settings)?;
+            println!("Configuration OK");
+            Ok(())
+        },
+        Command::ListChannels => {
+            let http = discord::build_http(settings)?;
+            for channel in discord::get_channels(&http, &settings.guild)? {
+                println!("{}\t{}", channel.id, channel);
+            }
+            Ok(())
+        },
+        Command::Send { channel, text } => {
+            let http = discord::build_http(settings)?;
+            let message = discord::send_message(&http, channel, text)?;
+            println!("Sent message {}", message.id);
+            Ok(())
+        },
+        Command::GatewayInfo => {
+            let http = discord::build_http(settings)?;
+            let info = discord::gateway_info(&http)?;
+            let limit = info.session_start_limit;
+            println!("Url: {}", info.url);
+            println!("Recommended shards: {}", info.shards);
+            println!("Session starts remaining: {}/{}, resets in {} s",
+                limit.remaining, limit.total, limit.reset_after / 1000);
+            Ok(())
+        },
+        Command::Help | Command::Version => Ok(()),
+    }
+}
 
 fn main() {
-    let settings = config::get_settings();
-    match discord::test_connection(&settings) {
-        Ok(_) => println!("Connection OK"),
-        Err(s) => {
-            println!("Error with connectiontest: {}", s);
-            println!("Closing application");
+    init_logging();
+    let args = match config::parse_arguments() {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            eprintln!("Try '{} --help' for more information", env!("CARGO_PKG_NAME"));
+            process::exit(2);
+        },
+    };
+    match args.command {
+        Command::Help => {
+            print!("{}", config::usage());
             return;
         },
+        Command::Version => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            return;
+        },
+        _ => {},
+    }
+
+    let settings = match config::get_settings(&args) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Could not load settings: {}", e);
+            error!("Closing application");
+            process::exit(1);
+        },
+    };
+    log::set_max_level(settings.log_level);
+    if let Err(e) = run_command(&args, &settings) {
+        error!("{} failed: {}", args.command, e);
+        process::exit(1);
     }
-    discord::start_bot(&settings);
 }